@@ -0,0 +1,343 @@
+#![cfg_attr(not(test), no_std)]
+
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, symbol_short, xdr::ToXdr, Address, BytesN,
+    Env, Symbol, Vec,
+};
+
+pub use dex_market_client::DexMarketInfo;
+
+/// Identifies a market, there can only be one market for each
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MarketKey {
+    /// The token address for the base asset
+    pub base_token: Address,
+
+    /// The token address for the quote asset
+    pub quote_token: Address,
+
+    /// The increment order prices must be a multiple of (U32F32 format)
+    pub price_tick: u64,
+}
+
+impl From<&DexMarketInfo> for MarketKey {
+    fn from(info: &DexMarketInfo) -> Self {
+        Self {
+            base_token: info.base_token.clone(),
+            quote_token: info.quote_token.clone(),
+            price_tick: info.price_tick,
+        }
+    }
+}
+
+pub trait DexFactory {
+    type Error;
+
+    fn init(env: Env, admin: Address, market_wasm_hash: BytesN<32>) -> Result<(), Self::Error>;
+    fn set_market_wasm(env: Env, market_wasm_hash: BytesN<32>) -> Result<(), Self::Error>;
+    fn market_wasm(env: Env) -> Result<BytesN<32>, Self::Error>;
+    fn create_market(
+        env: Env,
+        market_admin: Address,
+        info: DexMarketInfo,
+    ) -> Result<Address, Self::Error>;
+    fn market(env: Env, key: MarketKey) -> Option<Address>;
+    fn market_count(env: Env) -> u32;
+    fn markets(env: Env, start: u32, limit: u32) -> Vec<Address>;
+}
+
+#[contracterror]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum DexFactoryError {
+    NotInitialized = 100,
+    AlreadyInitialized = 101,
+    MarketExists = 102,
+}
+
+#[contract]
+pub struct DexFactoryContract;
+
+#[contractimpl]
+impl DexFactory for DexFactoryContract {
+    type Error = DexFactoryError;
+
+    /// Initialize the factory
+    ///
+    /// This can only be done once, and sets the admin which is allowed to create markets.
+    fn init(env: Env, admin: Address, market_wasm_hash: BytesN<32>) -> Result<(), DexFactoryError> {
+        if env.storage().instance().has(&ADMIN) {
+            return Err(DexFactoryError::AlreadyInitialized);
+        }
+
+        admin.require_auth();
+
+        env.storage().instance().set(&ADMIN, &admin);
+        env.storage()
+            .instance()
+            .set(&MARKET_WASM_HASH, &market_wasm_hash);
+
+        Ok(())
+    }
+
+    /// Change the wasm new markets are deployed from
+    ///
+    /// Markets that were already deployed are not affected.
+    fn set_market_wasm(env: Env, market_wasm_hash: BytesN<32>) -> Result<(), DexFactoryError> {
+        require_admin(&env)?;
+
+        env.storage()
+            .instance()
+            .set(&MARKET_WASM_HASH, &market_wasm_hash);
+
+        Ok(())
+    }
+
+    /// The hash of the wasm new markets are deployed from
+    fn market_wasm(env: Env) -> Result<BytesN<32>, DexFactoryError> {
+        env.storage()
+            .instance()
+            .get(&MARKET_WASM_HASH)
+            .ok_or(DexFactoryError::NotInitialized)
+    }
+
+    /// Deploy and initialize a new market
+    ///
+    /// The market is initialized in the same transaction it's deployed in, so nobody else
+    /// can initialize it first. Returns the address of the new market.
+    ///
+    /// Both the factory admin and the admin of the new market have to authorize this.
+    fn create_market(
+        env: Env,
+        market_admin: Address,
+        info: DexMarketInfo,
+    ) -> Result<Address, DexFactoryError> {
+        require_admin(&env)?;
+        market_admin.require_auth();
+
+        let key = MarketKey::from(&info);
+        let registry_key = (MARKET, key.clone());
+
+        if env.storage().persistent().has(&registry_key) {
+            return Err(DexFactoryError::MarketExists);
+        }
+
+        // the address of a market is derived from its key, so it can be known in advance
+        let salt = env.crypto().sha256(&key.clone().to_xdr(&env));
+        let market = env
+            .deployer()
+            .with_current_contract(salt)
+            .deploy(Self::market_wasm(env.clone())?);
+
+        dex_market_client::Client::new(&env, &market).init(&market_admin, &info);
+
+        let count = Self::market_count(env.clone());
+
+        env.storage().persistent().set(&registry_key, &market);
+        env.storage().persistent().set(&(MARKETS, count), &market);
+        env.storage().instance().set(&MARKET_COUNT, &(count + 1));
+
+        env.events().publish(
+            (symbol_short!("created"), key.base_token, key.quote_token),
+            (key.price_tick, market.clone()),
+        );
+
+        Ok(market)
+    }
+
+    /// The market deployed for a key, if there is one
+    fn market(env: Env, key: MarketKey) -> Option<Address> {
+        env.storage().persistent().get(&(MARKET, key))
+    }
+
+    /// The number of markets deployed by the factory
+    fn market_count(env: Env) -> u32 {
+        env.storage().instance().get(&MARKET_COUNT).unwrap_or(0)
+    }
+
+    /// Up to `limit` markets, in the order they were deployed, starting at index `start`
+    fn markets(env: Env, start: u32, limit: u32) -> Vec<Address> {
+        let end = Self::market_count(env.clone()).min(start.saturating_add(limit));
+        let mut markets = Vec::new(&env);
+
+        for index in start..end {
+            if let Some(market) = env.storage().persistent().get(&(MARKETS, index)) {
+                markets.push_back(market);
+            }
+        }
+
+        markets
+    }
+}
+
+fn require_admin(env: &Env) -> Result<Address, DexFactoryError> {
+    let admin: Address = env
+        .storage()
+        .instance()
+        .get(&ADMIN)
+        .ok_or(DexFactoryError::NotInitialized)?;
+
+    admin.require_auth();
+
+    Ok(admin)
+}
+
+/// Storage key for the address allowed to create markets
+const ADMIN: Symbol = symbol_short!("ADMIN");
+
+/// Storage key for the hash of the wasm markets are deployed from
+const MARKET_WASM_HASH: Symbol = symbol_short!("MKTWASM");
+
+/// Storage key for the number of markets deployed
+const MARKET_COUNT: Symbol = symbol_short!("MKTCOUNT");
+
+/// Storage key prefix for the market deployed for each key
+const MARKET: Symbol = symbol_short!("MARKET");
+
+/// Storage key prefix for the markets in the order they were deployed
+const MARKETS: Symbol = symbol_short!("MARKETS");
+
+#[cfg(test)]
+mod tests {
+    use soroban_sdk::testutils::Address as _;
+
+    use super::*;
+
+    use dex_market_client as dex_market;
+
+    struct TestEnv {
+        env: Env,
+        admin: Address,
+        factory: Address,
+    }
+
+    impl TestEnv {
+        fn new() -> Self {
+            let env = Env::default();
+            let factory = env.register_contract(None, DexFactoryContract);
+            let admin = Address::random(&env);
+
+            env.mock_all_auths();
+
+            // instantiating the market wasm takes more than the default budget
+            env.budget().reset_unlimited();
+
+            let wasm_hash = env.deployer().upload_contract_wasm(dex_market::WASM);
+            DexFactoryContractClient::new(&env, &factory).init(&admin, &wasm_hash);
+
+            Self {
+                env,
+                admin,
+                factory,
+            }
+        }
+
+        fn factory_client(&self) -> DexFactoryContractClient {
+            DexFactoryContractClient::new(&self.env, &self.factory)
+        }
+
+        fn market_info(&self, price_tick: u64) -> DexMarketInfo {
+            DexMarketInfo {
+                base_token: self.env.register_stellar_asset_contract(self.admin.clone()),
+                quote_token: self.env.register_stellar_asset_contract(self.admin.clone()),
+                base_min_order_size: 1,
+                price_tick,
+                fee_authority: self.admin.clone(),
+                maker_fee_bps: 0,
+                taker_fee_bps: 0,
+                base_decimals: 0,
+                quote_decimals: 0,
+            }
+        }
+    }
+
+    #[test]
+    fn test_create_market() {
+        let ctx = TestEnv::new();
+        let factory = ctx.factory_client();
+        let market_admin = Address::random(&ctx.env);
+
+        let info = ctx.market_info(1 << 24);
+        let market = factory.create_market(&market_admin, &info);
+
+        assert_eq!(
+            Some(market.clone()),
+            factory.market(&MarketKey::from(&info))
+        );
+        assert_eq!(1, factory.market_count());
+        assert_eq!(
+            Vec::from_array(&ctx.env, [market.clone()]),
+            factory.markets(&0, &10)
+        );
+
+        // the market is already initialized, with the requested admin
+        let market_client = dex_market::Client::new(&ctx.env, &market);
+        assert_eq!(
+            Err(Ok(dex_market::DexMarketError::AlreadyInitialized)),
+            market_client.try_init(
+                &ctx.admin,
+                &dex_market::DexMarketInfo {
+                    base_token: info.base_token.clone(),
+                    quote_token: info.quote_token.clone(),
+                    base_min_order_size: 1,
+                    price_tick: 1,
+                    fee_authority: ctx.admin.clone(),
+                    maker_fee_bps: 0,
+                    taker_fee_bps: 0,
+                    base_decimals: 0,
+                    quote_decimals: 0,
+                }
+            )
+        );
+
+        market_client.set_status(&dex_market::MarketStatus::Halted);
+        assert_eq!(market_admin, ctx.env.auths()[0].0);
+    }
+
+    #[test]
+    fn test_one_market_per_key() {
+        let ctx = TestEnv::new();
+        let factory = ctx.factory_client();
+        let market_admin = Address::random(&ctx.env);
+
+        let info = ctx.market_info(1 << 24);
+        let market = factory.create_market(&market_admin, &info);
+
+        assert_eq!(
+            Err(Ok(DexFactoryError::MarketExists)),
+            factory.try_create_market(&market_admin, &info)
+        );
+
+        // a different tick size is a different market
+        let other_tick = DexMarketInfo {
+            price_tick: 1 << 16,
+            ..info.clone()
+        };
+        let other_market = factory.create_market(&market_admin, &other_tick);
+
+        assert_ne!(market, other_market);
+        assert_eq!(2, factory.market_count());
+        assert_eq!(
+            Vec::from_array(&ctx.env, [other_market]),
+            factory.markets(&1, &10)
+        );
+    }
+
+    #[test]
+    fn test_failed_init_reverts_deploy() {
+        let ctx = TestEnv::new();
+        let factory = ctx.factory_client();
+
+        let info = DexMarketInfo {
+            maker_fee_bps: -100,
+            ..ctx.market_info(1)
+        };
+
+        assert!(factory
+            .try_create_market(&Address::random(&ctx.env), &info)
+            .is_err());
+        assert_eq!(None, factory.market(&MarketKey::from(&info)));
+        assert_eq!(0, factory.market_count());
+    }
+}
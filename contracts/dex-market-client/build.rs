@@ -0,0 +1,39 @@
+//! Build the market contract for wasm, so that the client can be generated from its interface
+//!
+//! The market is built into a target directory of its own, since the build directory of the
+//! crate being built is locked while this runs.
+
+use std::{env, path::PathBuf, process::Command};
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let workspace_dir = manifest_dir.join("../..");
+
+    for path in ["contracts/dex-market", "orderbook", "Cargo.toml"] {
+        println!(
+            "cargo:rerun-if-changed={}",
+            workspace_dir.join(path).display()
+        );
+    }
+
+    // the flags and wrappers of the outer build, such as clippy's, aren't meant for this one
+    let status = Command::new(env::var("CARGO").unwrap())
+        .current_dir(&workspace_dir)
+        .args([
+            "build",
+            "--package",
+            "dex-market",
+            "--target",
+            "wasm32-unknown-unknown",
+            "--release",
+            "--target-dir",
+        ])
+        .arg(workspace_dir.join("target/dex-market-client"))
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env_remove("RUSTC_WORKSPACE_WRAPPER")
+        .env_remove("CLIPPY_ARGS")
+        .status()
+        .expect("failed to run cargo");
+
+    assert!(status.success(), "failed to build the market contract");
+}
@@ -0,0 +1,12 @@
+//! A client for the `dex-market` contract, generated from the interface of its wasm
+//!
+//! The market crate can't be linked into another contract without also exporting the market's
+//! entrypoints from it, so contracts that call markets use this instead. The build script builds
+//! the market's wasm, so the client always matches the market in the same tree, and [WASM] can
+//! be uploaded to deploy markets in tests.
+
+#![no_std]
+
+soroban_sdk::contractimport!(
+    file = "../../target/dex-market-client/wasm32-unknown-unknown/release/dex_market.wasm"
+);
@@ -0,0 +1,41 @@
+use soroban_sdk::{symbol_short, Address, Env, Symbol};
+
+use crate::DexMarketError;
+
+/// Storage key for the address allowed to change the market configuration
+const ADMIN: Symbol = symbol_short!("ADMIN");
+
+/// Storage key for an admin that has been proposed, but has not accepted the role yet
+const PENDING_ADMIN: Symbol = symbol_short!("PENDADMIN");
+
+pub fn set_admin(env: &Env, admin: &Address) {
+    env.storage().instance().set(&ADMIN, admin);
+    crate::events::admin_changed(env, admin);
+}
+
+pub fn admin(env: &Env) -> Result<Address, DexMarketError> {
+    env.storage()
+        .instance()
+        .get(&ADMIN)
+        .ok_or(DexMarketError::NotInitialized)
+}
+
+/// Require authorization from the current admin
+pub fn require_admin(env: &Env) -> Result<Address, DexMarketError> {
+    let admin = admin(env)?;
+    admin.require_auth();
+
+    Ok(admin)
+}
+
+pub fn set_pending_admin(env: &Env, admin: &Address) {
+    env.storage().instance().set(&PENDING_ADMIN, admin);
+}
+
+/// Remove the proposed admin, returning it if there was one
+pub fn take_pending_admin(env: &Env) -> Option<Address> {
+    let pending = env.storage().instance().get(&PENDING_ADMIN);
+    env.storage().instance().remove(&PENDING_ADMIN);
+
+    pending
+}
@@ -0,0 +1,119 @@
+//! Addresses allowed to manage orders for their owners
+//!
+//! A delegate acts for an owner without ever holding the owner's tokens. The orders it places are
+//! funded from the owner's internal balance, and everything they pay out or refund goes to the
+//! owner.
+
+use soroban_sdk::{contracttype, symbol_short, Address, Env, Symbol};
+
+use crate::{DexMarketError, Funding};
+
+/// Storage key prefix for the permissions granted to each delegate of an owner
+const DELEGATE: Symbol = symbol_short!("DELEGATE");
+
+/// What a delegate is allowed to do for an owner
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DelegatePermissions {
+    /// The delegate can place orders
+    pub place: bool,
+
+    /// The delegate can cancel orders one at a time
+    pub cancel: bool,
+
+    /// The delegate can cancel all of the owner's orders at once
+    pub cancel_all: bool,
+
+    /// The largest quote value of an order the delegate can place (in quote tokens), or 0 for
+    /// no limit
+    pub max_notional: i128,
+
+    /// The last ledger the permissions can be used in
+    pub expiration_ledger: u32,
+}
+
+/// The actions a delegate can be allowed to take
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DelegateAction {
+    Place,
+    Cancel,
+    CancelAll,
+}
+
+/// Allow a delegate to act for an owner, replacing any permissions it had before
+pub fn grant(
+    env: &Env,
+    owner: &Address,
+    delegate: &Address,
+    permissions: &DelegatePermissions,
+) -> Result<(), DexMarketError> {
+    if permissions.max_notional < 0 {
+        return Err(DexMarketError::InvalidAmount);
+    }
+
+    if permissions.expiration_ledger < env.ledger().sequence() {
+        return Err(DexMarketError::InvalidExpiration);
+    }
+
+    env.storage()
+        .persistent()
+        .set(&(DELEGATE, owner.clone(), delegate.clone()), permissions);
+
+    Ok(())
+}
+
+pub fn revoke(env: &Env, owner: &Address, delegate: &Address) {
+    env.storage()
+        .persistent()
+        .remove(&(DELEGATE, owner.clone(), delegate.clone()));
+}
+
+/// The permissions a delegate has for an owner, if they haven't expired
+pub fn permissions(env: &Env, owner: &Address, delegate: &Address) -> Option<DelegatePermissions> {
+    env.storage()
+        .persistent()
+        .get::<_, DelegatePermissions>(&(DELEGATE, owner.clone(), delegate.clone()))
+        .filter(|permissions| permissions.expiration_ledger >= env.ledger().sequence())
+}
+
+/// Require authorization from a delegate which is allowed to take an action for an owner
+pub fn require_delegate(
+    env: &Env,
+    owner: &Address,
+    delegate: &Address,
+    action: DelegateAction,
+) -> Result<DelegatePermissions, DexMarketError> {
+    let permissions = permissions(env, owner, delegate).ok_or(DexMarketError::Unauthorized)?;
+
+    let is_allowed = match action {
+        DelegateAction::Place => permissions.place,
+        DelegateAction::Cancel => permissions.cancel,
+        DelegateAction::CancelAll => permissions.cancel_all,
+    };
+
+    if !is_allowed {
+        return Err(DexMarketError::Unauthorized);
+    }
+
+    delegate.require_auth();
+
+    Ok(permissions)
+}
+
+/// Check that a delegate can place an order for an owner
+///
+/// Orders placed by a delegate have to be funded from the owner's internal balance, so that a
+/// delegate can't spend tokens from the owner's account, even with an allowance the owner gave
+/// the market.
+pub fn check_order(
+    permissions: &DelegatePermissions,
+    funding: Funding,
+    notional: i128,
+) -> Result<(), DexMarketError> {
+    let is_within_limit = permissions.max_notional == 0 || notional <= permissions.max_notional;
+
+    match funding == Funding::Vault && is_within_limit {
+        true => Ok(()),
+        false => Err(DexMarketError::Unauthorized),
+    }
+}
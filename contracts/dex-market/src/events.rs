@@ -0,0 +1,208 @@
+//! Events published by the market
+//!
+//! Every event is published by the market contract, so the contract address identifies the
+//! market an event belongs to. The first topic is always the event name, followed by the
+//! addresses of the owners involved, so that events can be filtered by owner.
+
+use soroban_sdk::{contracttype, symbol_short, Address, BytesN, Env, Symbol, Vec};
+
+use crate::{DelegatePermissions, DexMarketInfo, MarketStatus, OrderId, OrderSide, RewardConfig};
+
+/// An order was posted to the book
+///
+/// Topics: `("placed", owner)`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderPlacedEvent {
+    /// The id of the posted order
+    pub order_id: OrderId,
+
+    /// The side of the book the order was posted to
+    pub side: OrderSide,
+
+    /// The price of the order (U32F32 format)
+    pub price: u64,
+
+    /// The size of the order that was requested (in base tokens)
+    pub size: u128,
+
+    /// The size of the order that was posted, after matching (in base tokens)
+    pub posted_size: u128,
+}
+
+/// A resting order was matched by a taker
+///
+/// Topics: `("trade", maker, taker)`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TradeEvent {
+    /// The id of the resting order that was filled
+    pub maker_order_id: OrderId,
+
+    /// The side of the book the resting order was on
+    pub maker_side: OrderSide,
+
+    /// The price of the fill (U32F32 format)
+    pub price: u64,
+
+    /// The size of the fill (in base tokens)
+    pub size: u128,
+
+    /// The value of the fill, before fees (in quote tokens)
+    pub quote_amount: i128,
+
+    /// The fee paid by the taker, in the token they received
+    pub taker_fee: i128,
+
+    /// The fee paid by the maker, in the token they received
+    pub maker_fee: i128,
+
+    /// The rebate paid to the maker, in the token the taker received
+    pub maker_rebate: i128,
+}
+
+/// A resting order was removed from the book before being filled
+///
+/// Topics: `("cancelled", owner)`
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderCancelledEvent {
+    /// The id of the cancelled order
+    pub order_id: OrderId,
+
+    /// The size of the order that was still unfilled (in base tokens)
+    pub unfilled_size: u128,
+}
+
+/// Topics: `("placed", owner)`
+pub fn order_placed(env: &Env, owner: &Address, event: OrderPlacedEvent) {
+    env.events()
+        .publish((symbol_short!("placed"), owner.clone()), event);
+}
+
+/// Topics: `("trade", maker, taker)`
+pub fn trade(env: &Env, maker: &Address, taker: &Address, event: TradeEvent) {
+    env.events().publish(
+        (symbol_short!("trade"), maker.clone(), taker.clone()),
+        event,
+    );
+}
+
+/// A resting order was completely filled and removed from the book
+///
+/// Topics: `("filled", owner)`, data: the order id
+pub fn order_filled(env: &Env, owner: &Address, order_id: &OrderId) {
+    env.events()
+        .publish((symbol_short!("filled"), owner.clone()), order_id.clone());
+}
+
+/// A resting order was moved to the book of a newer storage layout, under a new id
+///
+/// Topics: `("migrated", owner)`, data: `(old_id, new_id)`
+pub fn order_migrated(env: &Env, owner: &Address, old_id: &OrderId, new_id: &OrderId) {
+    env.events().publish(
+        (symbol_short!("migrated"), owner.clone()),
+        (old_id.clone(), new_id.clone()),
+    );
+}
+
+/// Topics: `("cancelled", owner)`
+pub fn order_cancelled(env: &Env, owner: &Address, event: OrderCancelledEvent) {
+    env.events()
+        .publish((symbol_short!("cancelled"), owner.clone()), event);
+}
+
+/// An owner allowed a delegate to manage their orders, or changed what it's allowed to do
+///
+/// Topics: `("delegate", owner, delegate)`, data: the new [DelegatePermissions]
+pub fn delegate_granted(
+    env: &Env,
+    owner: &Address,
+    delegate: &Address,
+    permissions: &DelegatePermissions,
+) {
+    env.events().publish(
+        (symbol_short!("delegate"), owner.clone(), delegate.clone()),
+        permissions.clone(),
+    );
+}
+
+/// An owner removed a delegate's permissions
+///
+/// Topics: `("revoked", owner, delegate)`
+pub fn delegate_revoked(env: &Env, owner: &Address, delegate: &Address) {
+    env.events().publish(
+        (symbol_short!("revoked"), owner.clone(), delegate.clone()),
+        (),
+    );
+}
+
+/// The market configuration was changed
+///
+/// Topics: `("config", "info")`, data: the new [DexMarketInfo]
+pub fn market_info_changed(env: &Env, info: &DexMarketInfo) {
+    publish_config(env, symbol_short!("info"), info.clone());
+}
+
+/// A new admin took over the market
+///
+/// Topics: `("config", "admin")`, data: the new admin
+pub fn admin_changed(env: &Env, admin: &Address) {
+    publish_config(env, symbol_short!("admin"), admin.clone());
+}
+
+/// The trading status of the market was changed
+///
+/// Topics: `("config", "status")`, data: the new [MarketStatus]
+pub fn status_changed(env: &Env, status: MarketStatus) {
+    publish_config(env, symbol_short!("status"), status);
+}
+
+/// The candle intervals tracked by the market were changed
+///
+/// Topics: `("config", "candles")`, data: the new intervals, in seconds
+pub fn candle_intervals_changed(env: &Env, intervals: &Vec<u64>) {
+    publish_config(env, symbol_short!("candles"), intervals.clone());
+}
+
+/// The length of the periods the oracle averages prices over was changed
+///
+/// Topics: `("config", "oracle")`, data: the new resolution, in seconds
+pub fn oracle_resolution_changed(env: &Env, resolution: u32) {
+    publish_config(env, symbol_short!("oracle"), resolution);
+}
+
+/// The share of the taker fee paid to referrers was changed
+///
+/// Topics: `("config", "referral")`, data: the new share, in basis points
+pub fn referral_share_changed(env: &Env, share_bps: u32) {
+    publish_config(env, symbol_short!("referral"), share_bps);
+}
+
+/// The way liquidity in the market is rewarded was changed
+///
+/// Topics: `("config", "rewards")`, data: the new [RewardConfig]
+pub fn reward_config_changed(env: &Env, config: &RewardConfig) {
+    publish_config(env, symbol_short!("rewards"), config.clone());
+}
+
+/// The code of the market was replaced
+///
+/// Topics: `("config", "wasm")`, data: the hash of the new wasm
+pub fn upgraded(env: &Env, wasm_hash: &BytesN<32>) {
+    publish_config(env, symbol_short!("wasm"), wasm_hash.clone());
+}
+
+/// The storage of the market was migrated to a new layout
+///
+/// Topics: `("config", "schema")`, data: the new version of the layout
+pub fn schema_migrated(env: &Env, version: u32) {
+    publish_config(env, symbol_short!("schema"), version);
+}
+
+fn publish_config<D>(env: &Env, name: Symbol, data: D)
+where
+    D: soroban_sdk::IntoVal<Env, soroban_sdk::Val>,
+{
+    env.events().publish((symbol_short!("config"), name), data);
+}
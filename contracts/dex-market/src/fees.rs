@@ -0,0 +1,149 @@
+use soroban_sdk::{symbol_short, Address, Env, Map, Symbol};
+
+use crate::{DexMarketError, DexMarketInfo};
+
+/// The number of basis points in a whole
+const BPS_SCALE: i128 = 10_000;
+
+/// Storage key for the fees accrued by the market, per token
+const ACCRUED_FEES: Symbol = symbol_short!("FEES");
+
+/// Storage key for the rounding dust kept by the market, per token
+const DUST: Symbol = symbol_short!("DUST");
+
+/// Check that a fee schedule can be applied without the market paying out more than it takes
+pub fn validate_schedule(info: &DexMarketInfo) -> Result<(), DexMarketError> {
+    let taker_fee_bps = info.taker_fee_bps as i64;
+    let maker_fee_bps = info.maker_fee_bps as i64;
+
+    // rebates are paid out of the taker fee, so they can never be larger than it
+    if taker_fee_bps > BPS_SCALE as i64
+        || maker_fee_bps > BPS_SCALE as i64
+        || maker_fee_bps < -taker_fee_bps
+    {
+        return Err(DexMarketError::InvalidFeeSchedule);
+    }
+
+    Ok(())
+}
+
+/// The fees charged for a single fill
+///
+/// Each side pays its fee out of the token it receives. A maker rebate is paid out of the
+/// taker fee, so it is denominated in the token the taker receives.
+pub struct FillFees {
+    /// The fee deducted from the amount received by the taker
+    pub taker_fee: i128,
+
+    /// The fee deducted from the amount received by the maker
+    pub maker_fee: i128,
+
+    /// The rebate paid to the maker, in the token received by the taker
+    pub maker_rebate: i128,
+}
+
+impl FillFees {
+    /// Calculate the fees for a fill
+    ///
+    /// # Params
+    ///
+    /// `maker_amount` - The amount of tokens the maker receives before fees
+    /// `taker_amount` - The amount of tokens the taker receives before fees
+    pub fn new(
+        info: &DexMarketInfo,
+        maker_amount: i128,
+        taker_amount: i128,
+    ) -> Result<Self, DexMarketError> {
+        let taker_fee = fee_amount(taker_amount, info.taker_fee_bps as i64)?;
+
+        Ok(match info.maker_fee_bps {
+            bps if bps >= 0 => Self {
+                taker_fee,
+                maker_fee: fee_amount(maker_amount, bps as i64)?,
+                maker_rebate: 0,
+            },
+
+            bps => Self {
+                taker_fee,
+                maker_fee: 0,
+                maker_rebate: rebate_amount(taker_amount, -bps as i64)?,
+            },
+        })
+    }
+}
+
+/// The fee at a rate in basis points, rounded up in favour of the market
+fn fee_amount(amount: i128, bps: i64) -> Result<i128, DexMarketError> {
+    amount
+        .checked_mul(bps as i128)
+        .and_then(|amount| amount.checked_add(BPS_SCALE - 1))
+        .map(|amount| amount / BPS_SCALE)
+        .ok_or(DexMarketError::ArithmeticOverflow)
+}
+
+/// The rebate at a rate in basis points, rounded down in favour of the market
+fn rebate_amount(amount: i128, bps: i64) -> Result<i128, DexMarketError> {
+    amount
+        .checked_mul(bps as i128)
+        .map(|amount| amount / BPS_SCALE)
+        .ok_or(DexMarketError::ArithmeticOverflow)
+}
+
+/// Add fees collected in a token
+pub fn accrue(env: &Env, token: &Address, amount: i128) -> Result<(), DexMarketError> {
+    add_amount(env, ACCRUED_FEES, token, amount)
+}
+
+/// The fees accrued by the market which have not been withdrawn yet
+#[cfg(test)]
+pub fn accrued(env: &Env) -> Map<Address, i128> {
+    amounts(env, ACCRUED_FEES)
+}
+
+/// Remove all accrued fees from the market's accounting, returning the amounts removed
+pub fn take_accrued(env: &Env) -> Map<Address, i128> {
+    take_amounts(env, ACCRUED_FEES)
+}
+
+/// The rounding dust kept by the market which has not been swept yet
+pub fn dust(env: &Env) -> Map<Address, i128> {
+    amounts(env, DUST)
+}
+
+/// Add the difference between what was rounded in and out of the market in a token
+pub fn accrue_dust(env: &Env, token: &Address, amount: i128) -> Result<(), DexMarketError> {
+    add_amount(env, DUST, token, amount)
+}
+
+/// Remove all dust from the market's accounting, returning the amounts removed
+pub fn take_dust(env: &Env) -> Map<Address, i128> {
+    take_amounts(env, DUST)
+}
+
+fn amounts(env: &Env, key: Symbol) -> Map<Address, i128> {
+    env.storage()
+        .instance()
+        .get(&key)
+        .unwrap_or_else(|| Map::new(env))
+}
+
+fn add_amount(env: &Env, key: Symbol, token: &Address, amount: i128) -> Result<(), DexMarketError> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let mut amounts = amounts(env, key.clone());
+    let current = amounts.get(token.clone()).unwrap_or(0);
+
+    amounts.set(token.clone(), crate::add_amounts(current, amount)?);
+    env.storage().instance().set(&key, &amounts);
+
+    Ok(())
+}
+
+fn take_amounts(env: &Env, key: Symbol) -> Map<Address, i128> {
+    let amounts = amounts(env, key.clone());
+    env.storage().instance().remove(&key);
+
+    amounts
+}
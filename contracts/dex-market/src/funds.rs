@@ -0,0 +1,397 @@
+use soroban_sdk::{contracttype, symbol_short, token, Address, Env, Symbol};
+
+use crate::{DexMarketError, DexMarketInfo};
+
+/// Storage key prefix for the internal balances of each user
+const BALANCE: Symbol = symbol_short!("BALANCE");
+
+/// Storage key prefix for payouts that could not be delivered to each user
+const UNSETTLED: Symbol = symbol_short!("UNSETTLED");
+
+/// Where the tokens for an order come from, and where its proceeds are paid to
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum Funding {
+    /// Tokens are transferred from and to the owner's account
+    Wallet = 0,
+
+    /// Tokens are locked in and credited to the owner's internal balance
+    Vault = 1,
+
+    /// Tokens are taken from the owner's account with an allowance they gave the market, and
+    /// paid to their account
+    ///
+    /// The owner doesn't have to authorize a token transfer when placing an order, only the
+    /// order itself.
+    Allowance = 2,
+}
+
+/// The internal balances a user holds in the market
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VaultBalance {
+    /// Base tokens available for new orders or withdrawal
+    pub base_free: i128,
+
+    /// Base tokens held for resting orders
+    pub base_locked: i128,
+
+    /// Quote tokens available for new orders or withdrawal
+    pub quote_free: i128,
+
+    /// Quote tokens held for resting orders
+    pub quote_locked: i128,
+}
+
+impl VaultBalance {
+    fn free_mut(&mut self, asset: Asset) -> &mut i128 {
+        match asset {
+            Asset::Base => &mut self.base_free,
+            Asset::Quote => &mut self.quote_free,
+        }
+    }
+
+    fn locked_mut(&mut self, asset: Asset) -> &mut i128 {
+        match asset {
+            Asset::Base => &mut self.base_locked,
+            Asset::Quote => &mut self.quote_locked,
+        }
+    }
+}
+
+/// Tokens owed to a user, which could not be transferred to them when their order was filled
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UnsettledFunds {
+    /// The amount of base tokens owed
+    pub base: i128,
+
+    /// The amount of quote tokens owed
+    pub quote: i128,
+}
+
+impl UnsettledFunds {
+    fn amount_mut(&mut self, asset: Asset) -> &mut i128 {
+        match asset {
+            Asset::Base => &mut self.base,
+            Asset::Quote => &mut self.quote,
+        }
+    }
+}
+
+/// One of the two tokens traded in the market
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Asset {
+    Base,
+    Quote,
+}
+
+pub fn vault_balance(env: &Env, owner: &Address) -> VaultBalance {
+    env.storage()
+        .persistent()
+        .get(&(BALANCE, owner.clone()))
+        .unwrap_or_default()
+}
+
+fn set_vault_balance(env: &Env, owner: &Address, balance: &VaultBalance) {
+    let key = (BALANCE, owner.clone());
+
+    match *balance == VaultBalance::default() {
+        true => env.storage().persistent().remove(&key),
+        false => env.storage().persistent().set(&key, balance),
+    }
+}
+
+pub fn unsettled_funds(env: &Env, owner: &Address) -> UnsettledFunds {
+    env.storage()
+        .persistent()
+        .get(&(UNSETTLED, owner.clone()))
+        .unwrap_or_default()
+}
+
+fn set_unsettled_funds(env: &Env, owner: &Address, unsettled: &UnsettledFunds) {
+    let key = (UNSETTLED, owner.clone());
+
+    match *unsettled == UnsettledFunds::default() {
+        true => env.storage().persistent().remove(&key),
+        false => env.storage().persistent().set(&key, unsettled),
+    }
+}
+
+/// Moves tokens between order owners and the market
+pub struct Funds<'a> {
+    env: &'a Env,
+    base: token::Client<'a>,
+    quote: token::Client<'a>,
+}
+
+impl<'a> Funds<'a> {
+    pub fn new(env: &'a Env, market_info: &DexMarketInfo) -> Self {
+        Self {
+            env,
+            base: token::Client::new(env, &market_info.base_token),
+            quote: token::Client::new(env, &market_info.quote_token),
+        }
+    }
+
+    fn token(&self, asset: Asset) -> &token::Client<'a> {
+        match asset {
+            Asset::Base => &self.base,
+            Asset::Quote => &self.quote,
+        }
+    }
+
+    fn update_vault(
+        &self,
+        owner: &Address,
+        update: impl FnOnce(&mut VaultBalance) -> Result<(), DexMarketError>,
+    ) -> Result<(), DexMarketError> {
+        let mut balance = vault_balance(self.env, owner);
+        update(&mut balance)?;
+
+        set_vault_balance(self.env, owner, &balance);
+
+        Ok(())
+    }
+
+    /// Move tokens from an owner's account into the market
+    pub fn deposit(
+        &self,
+        owner: &Address,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        self.transfer_in(owner, asset, amount)?;
+        self.update_vault(owner, |balance| add_to(balance.free_mut(asset), amount))
+    }
+
+    /// Move tokens from an owner's free internal balance to their account
+    pub fn withdraw(
+        &self,
+        owner: &Address,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        let mut balance = vault_balance(self.env, owner);
+        let free = balance.free_mut(asset);
+
+        if *free < amount {
+            return Err(DexMarketError::InsufficientBalance);
+        }
+
+        *free -= amount;
+        set_vault_balance(self.env, owner, &balance);
+
+        self.token(asset)
+            .transfer(&self.env.current_contract_address(), owner, &amount);
+
+        Ok(())
+    }
+
+    /// Take the tokens needed to place an order from its owner
+    pub fn collect(
+        &self,
+        owner: &Address,
+        funding: Funding,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        match funding {
+            Funding::Wallet => self.transfer_in(owner, asset, amount)?,
+            Funding::Allowance => self.transfer_from(owner, asset, amount)?,
+
+            Funding::Vault => {
+                let mut balance = vault_balance(self.env, owner);
+                let free = balance.free_mut(asset);
+
+                if *free < amount {
+                    return Err(DexMarketError::InsufficientBalance);
+                }
+
+                *free -= amount;
+                set_vault_balance(self.env, owner, &balance);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Transfer tokens from an owner's account to the market
+    ///
+    /// The balance is checked first, so that a shortfall is reported rather than failing in
+    /// the token contract.
+    fn transfer_in(
+        &self,
+        owner: &Address,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        let token = self.token(asset);
+
+        if token.balance(owner) < amount {
+            return Err(DexMarketError::InsufficientBalance);
+        }
+
+        token.transfer(owner, &self.env.current_contract_address(), &amount);
+
+        Ok(())
+    }
+
+    /// Transfer tokens from an owner's account to the market, using the owner's allowance
+    fn transfer_from(
+        &self,
+        owner: &Address,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        let token = self.token(asset);
+        let market = self.env.current_contract_address();
+
+        // an expired allowance is reported as nothing by the token
+        if token.allowance(owner, &market) < amount {
+            return Err(DexMarketError::InsufficientAllowance);
+        }
+
+        if token.balance(owner) < amount {
+            return Err(DexMarketError::InsufficientBalance);
+        }
+
+        token.transfer_from(&market, owner, &market, &amount);
+
+        Ok(())
+    }
+
+    /// Pay tokens held by the market to an owner
+    pub fn pay(
+        &self,
+        owner: &Address,
+        funding: Funding,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        match funding {
+            Funding::Wallet | Funding::Allowance => {
+                self.token(asset)
+                    .transfer(&self.env.current_contract_address(), owner, &amount);
+
+                Ok(())
+            }
+
+            Funding::Vault => {
+                self.update_vault(owner, |balance| add_to(balance.free_mut(asset), amount))
+            }
+        }
+    }
+
+    /// Pay tokens held by the market to an owner who is not part of the current transaction
+    ///
+    /// If the tokens can't be transferred to the owner's account, they are recorded as
+    /// unsettled instead, so that a single account can't block the transaction.
+    pub fn settle(
+        &self,
+        owner: &Address,
+        funding: Funding,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 {
+            return Ok(());
+        }
+
+        if funding != Funding::Vault {
+            let transfer = self.token(asset).try_transfer(
+                &self.env.current_contract_address(),
+                owner,
+                &amount,
+            );
+
+            if !matches!(transfer, Ok(Ok(()))) {
+                let mut unsettled = unsettled_funds(self.env, owner);
+                add_to(unsettled.amount_mut(asset), amount)?;
+
+                set_unsettled_funds(self.env, owner, &unsettled);
+            }
+
+            return Ok(());
+        }
+
+        self.pay(owner, funding, asset, amount)
+    }
+
+    /// Transfer all unsettled tokens to an owner, returning the amounts transferred
+    pub fn claim(&self, owner: &Address) -> Result<UnsettledFunds, DexMarketError> {
+        let unsettled = unsettled_funds(self.env, owner);
+        set_unsettled_funds(self.env, owner, &UnsettledFunds::default());
+
+        self.pay(owner, Funding::Wallet, Asset::Base, unsettled.base)?;
+        self.pay(owner, Funding::Wallet, Asset::Quote, unsettled.quote)?;
+
+        Ok(unsettled)
+    }
+
+    /// Hold tokens for the part of an order that rests in the book
+    pub fn lock(
+        &self,
+        owner: &Address,
+        funding: Funding,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 || funding != Funding::Vault {
+            return Ok(());
+        }
+
+        self.update_vault(owner, |balance| add_to(balance.locked_mut(asset), amount))
+    }
+
+    /// Use tokens held for a resting order, after it has been filled
+    pub fn spend_locked(
+        &self,
+        owner: &Address,
+        funding: Funding,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        if amount == 0 || funding != Funding::Vault {
+            return Ok(());
+        }
+
+        self.update_vault(owner, |balance| add_to(balance.locked_mut(asset), -amount))
+    }
+
+    /// Return the tokens held for a resting order to its owner
+    pub fn release(
+        &self,
+        owner: &Address,
+        funding: Funding,
+        asset: Asset,
+        amount: i128,
+    ) -> Result<(), DexMarketError> {
+        self.spend_locked(owner, funding, asset, amount)?;
+        self.settle(owner, funding, asset, amount)
+    }
+}
+
+/// Add to a balance, failing rather than overflowing
+fn add_to(balance: &mut i128, amount: i128) -> Result<(), DexMarketError> {
+    *balance = crate::add_amounts(*balance, amount)?;
+
+    Ok(())
+}
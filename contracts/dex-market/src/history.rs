@@ -0,0 +1,272 @@
+use soroban_sdk::{contracttype, symbol_short, vec, Env, Symbol, Vec};
+
+use crate::DexMarketError;
+
+/// Storage key for the candle intervals tracked by the market
+const CANDLE_INTERVALS: Symbol = symbol_short!("CANDLEINT");
+
+/// Storage key prefix for the candles recorded for each interval
+const CANDLES: Symbol = symbol_short!("CANDLES");
+
+/// Storage key for the last trade in the market
+const LAST_TRADE: Symbol = symbol_short!("LASTTRADE");
+
+/// Storage key for the hourly volume buckets covering the last day
+const VOLUME: Symbol = symbol_short!("VOLUME");
+
+/// The number of candles kept for each interval
+pub const MAX_CANDLES: u32 = 64;
+
+/// The maximum number of candle intervals a market can track
+pub const MAX_CANDLE_INTERVALS: u32 = 4;
+
+/// The candle intervals used until the admin configures them, in seconds
+const DEFAULT_CANDLE_INTERVALS: [u64; 3] = [60, 3_600, 86_400];
+
+const SECONDS_PER_HOUR: u64 = 3_600;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// The price and volume of trades within a time interval
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Candle {
+    /// The ledger timestamp the interval starts at
+    pub start: u64,
+
+    /// The price of the first trade in the interval (U32F32 format)
+    pub open: u64,
+
+    /// The highest price traded in the interval (U32F32 format)
+    pub high: u64,
+
+    /// The lowest price traded in the interval (U32F32 format)
+    pub low: u64,
+
+    /// The price of the last trade in the interval (U32F32 format)
+    pub close: u64,
+
+    /// The amount traded in the interval (in base tokens)
+    pub base_volume: u128,
+
+    /// The value traded in the interval (in quote tokens)
+    pub quote_volume: i128,
+}
+
+/// The most recent trade in the market
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LastTrade {
+    /// The price of the trade (U32F32 format)
+    pub price: u64,
+
+    /// The ledger timestamp of the trade
+    pub timestamp: u64,
+}
+
+/// The amount traded over a period
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Volume {
+    /// The amount traded (in base tokens)
+    pub base: u128,
+
+    /// The value traded (in quote tokens)
+    pub quote: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct VolumeBucket {
+    hour: u64,
+    base: u128,
+    quote: i128,
+}
+
+/// The trades made by a single order, which are recorded together
+#[derive(Default)]
+pub struct TradeStats {
+    first: Option<u64>,
+    last: u64,
+    high: u64,
+    low: u64,
+    base_volume: u128,
+    quote_volume: i128,
+}
+
+impl TradeStats {
+    pub fn add(&mut self, price: u64, base_amount: u128, quote_amount: i128) {
+        if self.first.is_none() {
+            self.first = Some(price);
+            self.high = price;
+            self.low = price;
+        }
+
+        self.last = price;
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+
+        // volumes are only informational, so they saturate rather than failing the trade
+        self.base_volume = self.base_volume.saturating_add(base_amount);
+        self.quote_volume = self.quote_volume.saturating_add(quote_amount);
+    }
+
+    /// The price of the last trade, if there were any
+    pub fn last_price(&self) -> Option<u64> {
+        self.first.map(|_| self.last)
+    }
+}
+
+pub fn candle_intervals(env: &Env) -> Vec<u64> {
+    env.storage()
+        .instance()
+        .get(&CANDLE_INTERVALS)
+        .unwrap_or_else(|| Vec::from_array(env, DEFAULT_CANDLE_INTERVALS))
+}
+
+/// Change the candle intervals tracked by the market
+///
+/// Candles recorded for intervals which are no longer tracked are removed.
+pub fn set_candle_intervals(env: &Env, intervals: &Vec<u64>) -> Result<(), DexMarketError> {
+    if intervals.len() > MAX_CANDLE_INTERVALS {
+        return Err(DexMarketError::InvalidCandleInterval);
+    }
+
+    for (index, interval) in intervals.iter().enumerate() {
+        let is_duplicate = intervals.first_index_of(interval) != Some(index as u32);
+
+        if interval == 0 || is_duplicate {
+            return Err(DexMarketError::InvalidCandleInterval);
+        }
+    }
+
+    for interval in candle_intervals(env).iter() {
+        if !intervals.contains(interval) {
+            env.storage().persistent().remove(&(CANDLES, interval));
+        }
+    }
+
+    env.storage().instance().set(&CANDLE_INTERVALS, intervals);
+
+    Ok(())
+}
+
+/// The most recent candles for an interval, oldest first
+pub fn candles(env: &Env, interval: u64) -> Vec<Candle> {
+    env.storage()
+        .persistent()
+        .get(&(CANDLES, interval))
+        .unwrap_or_else(|| vec![env])
+}
+
+pub fn last_trade(env: &Env) -> Option<LastTrade> {
+    env.storage().instance().get(&LAST_TRADE)
+}
+
+/// The volume traded in roughly the last 24 hours
+///
+/// Volume is tracked in hourly buckets, so this includes trades made up to an hour earlier.
+pub fn volume_24h(env: &Env) -> Volume {
+    let now = env.ledger().timestamp();
+    let mut volume = Volume::default();
+
+    for bucket in volume_buckets(env, now).iter() {
+        volume.base = volume.base.saturating_add(bucket.base);
+        volume.quote = volume.quote.saturating_add(bucket.quote);
+    }
+
+    volume
+}
+
+/// Record the trades made by an order at the current ledger time
+pub fn record(env: &Env, stats: &TradeStats) {
+    let Some(open) = stats.first else {
+        return;
+    };
+
+    let now = env.ledger().timestamp();
+
+    env.storage().instance().set(
+        &LAST_TRADE,
+        &LastTrade {
+            price: stats.last,
+            timestamp: now,
+        },
+    );
+
+    record_volume(env, now, stats);
+
+    for interval in candle_intervals(env).iter() {
+        let mut candles = candles(env, interval);
+        let start = now - now % interval;
+
+        match candles.last() {
+            Some(mut candle) if candle.start == start => {
+                candle.high = candle.high.max(stats.high);
+                candle.low = candle.low.min(stats.low);
+                candle.close = stats.last;
+                candle.base_volume = candle.base_volume.saturating_add(stats.base_volume);
+                candle.quote_volume = candle.quote_volume.saturating_add(stats.quote_volume);
+
+                candles.set(candles.len() - 1, candle);
+            }
+
+            _ => {
+                if candles.len() == MAX_CANDLES {
+                    candles.pop_front();
+                }
+
+                candles.push_back(Candle {
+                    start,
+                    open,
+                    high: stats.high,
+                    low: stats.low,
+                    close: stats.last,
+                    base_volume: stats.base_volume,
+                    quote_volume: stats.quote_volume,
+                });
+            }
+        }
+
+        env.storage()
+            .persistent()
+            .set(&(CANDLES, interval), &candles);
+    }
+}
+
+/// The volume buckets that are still within a day of the given time
+fn volume_buckets(env: &Env, now: u64) -> Vec<VolumeBucket> {
+    let oldest_hour = now.saturating_sub(SECONDS_PER_DAY) / SECONDS_PER_HOUR;
+    let mut buckets: Vec<VolumeBucket> = env
+        .storage()
+        .instance()
+        .get(&VOLUME)
+        .unwrap_or_else(|| vec![env]);
+
+    while matches!(buckets.first(), Some(bucket) if bucket.hour <= oldest_hour) {
+        buckets.pop_front();
+    }
+
+    buckets
+}
+
+fn record_volume(env: &Env, now: u64, stats: &TradeStats) {
+    let hour = now / SECONDS_PER_HOUR;
+    let mut buckets = volume_buckets(env, now);
+
+    match buckets.last() {
+        Some(mut bucket) if bucket.hour == hour => {
+            bucket.base = bucket.base.saturating_add(stats.base_volume);
+            bucket.quote = bucket.quote.saturating_add(stats.quote_volume);
+
+            buckets.set(buckets.len() - 1, bucket);
+        }
+
+        _ => buckets.push_back(VolumeBucket {
+            hour,
+            base: stats.base_volume,
+            quote: stats.quote_volume,
+        }),
+    }
+
+    env.storage().instance().set(&VOLUME, &buckets);
+}
@@ -1,16 +1,50 @@
 #![cfg_attr(not(test), no_std)]
 
-use fixed::types::U96F32;
-use orderbook::OrderBook;
+mod admin;
+mod delegates;
+mod events;
+mod fees;
+mod funds;
+mod history;
+mod migration;
+mod oracle;
+mod owner_orders;
+mod price;
+mod referrals;
+mod rewards;
+mod status;
+mod swap;
+
+use delegates::DelegateAction;
+use fees::FillFees;
+use funds::{Asset, Funds};
+use orderbook::{OrderBook, OrderEntry, OrderSummary};
+use price::QuoteValue;
 use soroban_sdk::{
-    contract, contracterror, contractimpl, contracttype, symbol_short, token, Address, Env, Map,
-    Symbol,
+    contract, contracterror, contractimpl, contracttype, symbol_short, token, Address, BytesN, Env,
+    Map, Symbol, Vec,
 };
 
-pub use orderbook::OrderId;
+pub use delegates::DelegatePermissions;
+pub use events::{OrderCancelledEvent, OrderPlacedEvent, TradeEvent};
+pub use funds::{Funding, UnsettledFunds, VaultBalance};
+pub use history::{Candle, LastTrade, Volume};
+pub use oracle::PriceData;
+pub use orderbook::{OrderEvent, OrderId};
+pub use referrals::{ReferralEarnings, Referrer};
+pub use rewards::RewardConfig;
+pub use status::MarketStatus;
+
+/// The largest size an order can have (in base tokens)
+///
+/// Sizes are converted to `i128` token amounts, and multiplied by prices to find the value of an
+/// order, so they are kept small enough for both. Orders whose value still doesn't fit in a
+/// token amount fail with [DexMarketError::AmountTooLarge].
+pub const MAX_ORDER_SIZE: u128 = u64::MAX as u128;
 
 /// Specifies the side of the book an order is placed on
 #[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum OrderSide {
     Bid,
     Ask,
@@ -25,15 +59,22 @@ pub struct OrderParams {
     /// The size of the order (in base tokens)
     pub size: u128,
 
-    /// The price of the order (U32F32 format) (in quote tokens)
+    /// The price of the order (U32F32 format) (in whole quote tokens per whole base token)
     pub price: u64,
 
     /// The owning address of the order
     pub owner: Address,
+
+    /// Where the tokens for the order come from, and where its proceeds go
+    pub funding: Funding,
+
+    /// The address which earns a share of the taker fee paid by the order
+    pub referrer: Referrer,
 }
 
 /// The configuration for a trading market
 #[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct DexMarketInfo {
     /// The token address for the base asset
     pub base_token: Address,
@@ -43,14 +84,230 @@ pub struct DexMarketInfo {
 
     /// The minimum order size
     pub base_min_order_size: u128,
+
+    /// The increment order prices must be a multiple of (U32F32 format)
+    pub price_tick: u64,
+
+    /// The address allowed to withdraw the fees collected by the market
+    pub fee_authority: Address,
+
+    /// The fee charged to makers, in basis points of the amount they receive
+    ///
+    /// A negative fee is a rebate, which is paid out of the taker fee
+    pub maker_fee_bps: i32,
+
+    /// The fee charged to takers, in basis points of the amount they receive
+    pub taker_fee_bps: u32,
+
+    /// The number of decimals of the base token, read from the token when the market is initialized
+    pub base_decimals: u32,
+
+    /// The number of decimals of the quote token, read from the token when the market is initialized
+    pub quote_decimals: u32,
+}
+
+/// The result of filling an order against the current book
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FillQuote {
+    /// The amount the trader would pay
+    pub amount_in: i128,
+
+    /// The amount the trader would receive, after fees
+    pub amount_out: i128,
+}
+
+/// A summary of the matching done when placing an order
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FillSummary {
+    /// The size of the order that was posted (in base tokens)
+    pub posted_size: u128,
+
+    /// The size of the order that was filled (in base tokens)
+    pub base_filled: u128,
+
+    /// The quote value of the fills, before fees (in quote tokens)
+    pub quote_filled: i128,
+
+    /// The fee paid by the order owner, in the token they received
+    pub taker_fee: i128,
+
+    /// The net fees kept by the market in base tokens, after rebates and referral fees
+    pub base_fees: i128,
+
+    /// The net fees kept by the market in quote tokens, after rebates and referral fees
+    pub quote_fees: i128,
+}
+
+impl FillSummary {
+    /// The amount the order owner paid for the fills
+    fn amount_in(&self, side: OrderSide) -> i128 {
+        match side {
+            OrderSide::Bid => self.quote_filled,
+            OrderSide::Ask => self.base_filled as i128,
+        }
+    }
+
+    /// The amount the order owner received from the fills, after fees
+    fn amount_out(&self, side: OrderSide) -> i128 {
+        match side {
+            OrderSide::Bid => self.base_filled as i128 - self.taker_fee,
+            OrderSide::Ask => self.quote_filled - self.taker_fee,
+        }
+    }
+}
+
+/// An order resting in the book
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OrderInfo {
+    /// The id of the order
+    pub id: OrderId,
+
+    /// The owning address of the order
+    pub owner: Address,
+
+    /// Where the tokens for the order come from, and where its proceeds go
+    pub funding: Funding,
+
+    /// The side of the book the order rests on
+    pub side: OrderSide,
+
+    /// The price of the order (U32F32 format)
+    pub price: u64,
+
+    /// The size of the order which has not been filled yet (in base tokens)
+    pub remaining_size: u128,
+
+    /// Events for the order which have not been processed yet
+    pub pending_events: Vec<OrderEvent>,
 }
 
 pub trait DexMarket {
     type Error;
 
-    fn init(env: Env, info: DexMarketInfo);
-    fn place_order(env: Env, params: OrderParams) -> Result<Option<OrderId>, Self::Error>;
-    fn cancel_order(env: Env, order: OrderId);
+    fn init(env: Env, admin: Address, info: DexMarketInfo) -> Result<(), Self::Error>;
+    fn place_order(
+        env: Env,
+        params: OrderParams,
+    ) -> Result<(Option<OrderId>, FillSummary), Self::Error>;
+    fn place_order_as(
+        env: Env,
+        delegate: Address,
+        params: OrderParams,
+    ) -> Result<(Option<OrderId>, FillSummary), Self::Error>;
+    fn quote_fill(
+        env: Env,
+        side: OrderSide,
+        max_in: i128,
+        max_out: i128,
+    ) -> Result<FillQuote, Self::Error>;
+    fn swap_exact_in(
+        env: Env,
+        trader: Address,
+        side: OrderSide,
+        amount_in: i128,
+        min_out: i128,
+    ) -> Result<(i128, i128), Self::Error>;
+    fn swap_exact_out(
+        env: Env,
+        trader: Address,
+        side: OrderSide,
+        amount_out: i128,
+        max_in: i128,
+    ) -> Result<i128, Self::Error>;
+    fn cancel_order(env: Env, order: OrderId) -> Result<(), Self::Error>;
+    fn cancel_order_as(env: Env, delegate: Address, order: OrderId) -> Result<(), Self::Error>;
+    fn cancel_all_orders(env: Env, owner: Address, max_orders: u32) -> Result<u32, Self::Error>;
+    fn cancel_all_orders_as(
+        env: Env,
+        delegate: Address,
+        owner: Address,
+        max_orders: u32,
+    ) -> Result<u32, Self::Error>;
+    fn grant_delegate(
+        env: Env,
+        owner: Address,
+        delegate: Address,
+        permissions: DelegatePermissions,
+    ) -> Result<(), Self::Error>;
+    fn revoke_delegate(env: Env, owner: Address, delegate: Address) -> Result<(), Self::Error>;
+    fn delegate_permissions(
+        env: Env,
+        owner: Address,
+        delegate: Address,
+    ) -> Option<DelegatePermissions>;
+    fn market_info(env: Env) -> Result<DexMarketInfo, Self::Error>;
+    fn get_order(env: Env, order: OrderId) -> Result<OrderInfo, Self::Error>;
+    fn owner_orders(
+        env: Env,
+        owner: Address,
+        after: Option<OrderId>,
+        max_orders: u32,
+    ) -> Vec<OrderId>;
+    fn best_bid_ask(env: Env) -> (Option<u64>, Option<u64>);
+    fn spread(env: Env) -> Option<u64>;
+    fn mid_price(env: Env) -> Option<u64>;
+    fn withdraw_fees(env: Env, to: Address) -> Result<(), Self::Error>;
+    fn dust(env: Env) -> Map<Address, i128>;
+    fn sweep_dust(env: Env, to: Address) -> Result<(), Self::Error>;
+    fn set_fee_schedule(
+        env: Env,
+        maker_fee_bps: i32,
+        taker_fee_bps: u32,
+    ) -> Result<(), Self::Error>;
+    fn set_fee_authority(env: Env, fee_authority: Address) -> Result<(), Self::Error>;
+    fn set_min_order_size(env: Env, base_min_order_size: u128) -> Result<(), Self::Error>;
+    fn set_admin(env: Env, new_admin: Address) -> Result<(), Self::Error>;
+    fn accept_admin(env: Env) -> Result<(), Self::Error>;
+    fn set_status(env: Env, status: MarketStatus) -> Result<(), Self::Error>;
+    fn status(env: Env) -> MarketStatus;
+    fn upgrade(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Self::Error>;
+    fn schema_version(env: Env) -> u32;
+    fn migrate(env: Env) -> Result<bool, Self::Error>;
+    fn migrate_orders(env: Env, max_orders: u32) -> Result<bool, Self::Error>;
+    fn close_market(env: Env) -> Result<(), Self::Error>;
+    fn settle_closing(env: Env, max_orders: u32) -> Result<bool, Self::Error>;
+    fn deposit(
+        env: Env,
+        owner: Address,
+        base_amount: i128,
+        quote_amount: i128,
+    ) -> Result<(), Self::Error>;
+    fn withdraw(
+        env: Env,
+        owner: Address,
+        base_amount: i128,
+        quote_amount: i128,
+    ) -> Result<(), Self::Error>;
+    fn balance(env: Env, owner: Address) -> VaultBalance;
+    fn claim(env: Env, owner: Address) -> Result<UnsettledFunds, Self::Error>;
+    fn unsettled(env: Env, owner: Address) -> UnsettledFunds;
+    fn set_referral_share(env: Env, share_bps: u32) -> Result<(), Self::Error>;
+    fn referral_share(env: Env) -> u32;
+    fn claim_referral_fees(env: Env, referrer: Address) -> Result<ReferralEarnings, Self::Error>;
+    fn referral_earnings(env: Env, referrer: Address) -> ReferralEarnings;
+    fn set_reward_config(env: Env, config: RewardConfig) -> Result<(), Self::Error>;
+    fn reward_config(env: Env) -> Option<RewardConfig>;
+    fn fund_rewards(env: Env, from: Address, amount: i128) -> Result<(), Self::Error>;
+    fn reward_pool(env: Env) -> i128;
+    fn claim_rewards(env: Env, owner: Address) -> Result<i128, Self::Error>;
+    fn rewards(env: Env, owner: Address) -> i128;
+    fn set_candle_intervals(env: Env, intervals: Vec<u64>) -> Result<(), Self::Error>;
+    fn candle_intervals(env: Env) -> Vec<u64>;
+    fn candles(env: Env, interval: u64, limit: u32) -> Vec<Candle>;
+    fn last_trade(env: Env) -> Option<LastTrade>;
+    fn volume_24h(env: Env) -> Volume;
+    fn set_oracle_resolution(env: Env, resolution: u32) -> Result<(), Self::Error>;
+    fn base(env: Env) -> Result<Address, Self::Error>;
+    fn assets(env: Env) -> Result<Vec<Address>, Self::Error>;
+    fn decimals(env: Env) -> u32;
+    fn resolution(env: Env) -> u32;
+    fn price(env: Env, asset: Address, timestamp: u64) -> Option<PriceData>;
+    fn lastprice(env: Env, asset: Address) -> Option<PriceData>;
+    fn prices(env: Env, asset: Address, records: u32) -> Option<Vec<PriceData>>;
+    fn twap(env: Env, asset: Address, periods: u32) -> Option<PriceData>;
 }
 
 impl From<OrderSide> for orderbook::OrderSide {
@@ -62,12 +319,118 @@ impl From<OrderSide> for orderbook::OrderSide {
     }
 }
 
+impl From<orderbook::OrderSide> for OrderSide {
+    fn from(value: orderbook::OrderSide) -> Self {
+        match value {
+            orderbook::OrderSide::Bid => OrderSide::Bid,
+            orderbook::OrderSide::Ask => OrderSide::Ask,
+        }
+    }
+}
+
 #[contracterror]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 #[repr(u32)]
 pub enum DexMarketError {
+    /// The order is smaller than the market's minimum order size
     InvalidOrderSize = 100,
+
+    /// The order would have matched against another order from the same owner
     CannotSelfTrade = 101,
+
+    /// The order id is malformed, or belongs to a different market
+    InvalidOrderId = 102,
+
+    /// The fees are out of range, or the maker rebate is larger than the taker fee
+    InvalidFeeSchedule = 103,
+
+    /// The market hasn't been initialized yet
+    NotInitialized = 104,
+
+    /// The market has already been initialized
+    AlreadyInitialized = 105,
+
+    /// There is no admin transfer to accept
+    NoPendingAdmin = 106,
+
+    /// The market is halted, so nothing can be placed or cancelled
+    MarketHalted = 107,
+
+    /// The market only allows orders to be cancelled
+    MarketCancelOnly = 108,
+
+    /// The market only allows orders which don't take liquidity
+    MarketPostOnly = 109,
+
+    /// The market is closing, and its resting orders are being returned
+    MarketClosing = 110,
+
+    /// The market has been closed for good
+    MarketClosed = 111,
+
+    /// The market has to be closing to do this
+    MarketNotClosing = 112,
+
+    /// The owner doesn't have enough tokens, in their account or their internal balance
+    InsufficientBalance = 113,
+
+    /// An amount is negative, or zero where something has to be moved
+    InvalidAmount = 114,
+
+    /// A candle interval is zero or repeated, or there are too many of them
+    InvalidCandleInterval = 115,
+
+    /// The oracle resolution is zero
+    InvalidResolution = 116,
+
+    /// The price tick is zero
+    InvalidTickSize = 117,
+
+    /// The price is zero, too large, or not a multiple of the price tick
+    InvalidPrice = 118,
+
+    /// The trade would pay more or receive less than the trader's limit
+    SlippageExceeded = 119,
+
+    /// The book doesn't have enough liquidity to fill the trade
+    InsufficientLiquidity = 120,
+
+    /// The decimals of the tokens are too far apart to convert between them
+    InvalidDecimals = 121,
+
+    /// An intermediate value in a calculation doesn't fit
+    ArithmeticOverflow = 122,
+
+    /// An amount is larger than the market can handle
+    AmountTooLarge = 123,
+
+    /// The order has already been filled or cancelled, or never existed
+    OrderNotFound = 124,
+
+    /// The caller isn't allowed to act for the owner, or not in this way
+    Unauthorized = 125,
+
+    /// The expiration ledger has already passed
+    InvalidExpiration = 126,
+
+    /// The owner's allowance for the market is too low, or has expired
+    InsufficientAllowance = 127,
+
+    /// The storage was written by a newer version of the contract
+    UnsupportedSchemaVersion = 128,
+
+    /// An order can't be referred by its owner
+    InvalidReferrer = 129,
+
+    /// Liquidity rewards have not been configured for the market
+    RewardsNotConfigured = 130,
+
+    /// The emission rate is negative, the band is empty or wider than the price, the minimum
+    /// order size is zero, or the reward token would change
+    InvalidRewardConfig = 131,
+
+    /// The market has to be migrated to the current storage layout before it can be used
+    MigrationRequired = 132,
 }
 
 #[contract]
@@ -78,363 +441,3861 @@ impl DexMarket for DexMarketContract {
     type Error = DexMarketError;
 
     /// Initialize a new market
-    fn init(env: Env, info: DexMarketInfo) {
-        env.storage().instance().set(&MARKET_INFO, &info);
+    ///
+    /// This can only be done once, and sets the admin which is allowed to change the
+    /// market configuration afterwards.
+    fn init(env: Env, admin: Address, info: DexMarketInfo) -> Result<(), DexMarketError> {
+        if env.storage().instance().has(&MARKET_INFO) {
+            return Err(DexMarketError::AlreadyInitialized);
+        }
+
+        admin.require_auth();
+        fees::validate_schedule(&info)?;
+
+        if info.price_tick == 0 {
+            return Err(DexMarketError::InvalidTickSize);
+        }
+
+        let info = DexMarketInfo {
+            base_decimals: token::Client::new(&env, &info.base_token).decimals(),
+            quote_decimals: token::Client::new(&env, &info.quote_token).decimals(),
+            ..info
+        };
+
+        price::validate_decimals(info.base_decimals, info.quote_decimals)?;
+
+        admin::set_admin(&env, &admin);
+        migration::set_schema_version(&env, migration::CURRENT_SCHEMA_VERSION);
+        set_market_info(&env, &info);
+
+        Ok(())
     }
 
     /// Place a new order in the market
-    fn place_order(env: Env, params: OrderParams) -> Result<Option<OrderId>, DexMarketError> {
-        use orderbook::OrderSide;
+    ///
+    /// Returns the id of the order if any of it was posted to the book, along with a
+    /// summary of the fills and fees
+    fn place_order(
+        env: Env,
+        params: OrderParams,
+    ) -> Result<(Option<OrderId>, FillSummary), DexMarketError> {
+        submit_order(&env, params, |_, params| {
+            params.details.owner.require_auth();
 
-        let order_book = order_book_state(&env);
-        let params = orderbook::OrderParams {
-            side: params.side.into(),
-            size: params.size,
-            price: params.price,
-            details: OrderDetail {
-                owner: params.owner,
-            },
-        };
+            Ok(())
+        })
+    }
+
+    /// Place a new order for an owner, as one of their delegates
+    ///
+    /// The delegate has to be allowed to place orders, and the order has to be funded from the
+    /// owner's internal balance, within the delegate's notional limit.
+    fn place_order_as(
+        env: Env,
+        delegate: Address,
+        params: OrderParams,
+    ) -> Result<(Option<OrderId>, FillSummary), DexMarketError> {
+        submit_order(&env, params, |market_info, params| {
+            let owner = &params.details.owner;
+            let permissions =
+                delegates::require_delegate(&env, owner, &delegate, DelegateAction::Place)?;
+            let notional = price::bid_escrow(market_info, params.price, params.size)?;
+
+            delegates::check_order(&permissions, params.details.funding, notional)
+        })
+    }
 
-        let market_info: DexMarketInfo = env.storage().instance().get(&MARKET_INFO).unwrap();
-        let base = token::Client::new(&env, &market_info.base_token);
-        let quote = token::Client::new(&env, &market_info.quote_token);
+    /// The amounts an order filled against the current book would trade, without trading them
+    ///
+    /// The order takes liquidity until the trader would have paid `max_in`, or would have
+    /// received at least `max_out` after fees, whichever comes first. This is how much
+    /// `swap_exact_in` and `swap_exact_out` would trade for the same limits, so a route through
+    /// several markets can work out what each of them needs to deliver before it trades.
+    fn quote_fill(
+        env: Env,
+        side: OrderSide,
+        max_in: i128,
+        max_out: i128,
+    ) -> Result<FillQuote, DexMarketError> {
+        let market_info = market_info(&env)?;
 
-        if params.size < market_info.base_min_order_size {
-            return Err(DexMarketError::InvalidOrderSize);
+        if max_in < 0 || max_out < 0 {
+            return Err(DexMarketError::InvalidAmount);
         }
 
-        params.details.owner.require_auth();
-        let quote_offer_amount = quote_amount(params.price, params.size);
+        let order_book = order_book_state(&env);
+        let plan = swap::FillPlan::new(&order_book, &market_info, side.into(), max_in, max_out)?;
 
-        match params.side {
-            OrderSide::Bid => {
-                quote.transfer(
-                    &params.details.owner,
-                    &env.current_contract_address(),
-                    &quote_offer_amount,
-                );
-            }
+        Ok(FillQuote {
+            amount_in: plan.amount_in,
+            amount_out: plan.amount_out,
+        })
+    }
 
-            OrderSide::Ask => {
-                base.transfer(
-                    &params.details.owner,
-                    &env.current_contract_address(),
-                    &(params.size as i128),
-                );
-            }
+    /// Swap up to `amount_in` of the token paid for an order, for at least `min_out`
+    ///
+    /// The trader pays in quote tokens and receives base tokens for bids, and the other way
+    /// around for asks. The order takes as much of the book as `amount_in` pays for, and nothing
+    /// is posted to the book. Whatever the book can't take, including what's left of a bid that
+    /// can't buy a whole base token, stays with the trader. The swap fails with
+    /// `InsufficientLiquidity` if nothing can be filled, and with `SlippageExceeded` if the trader
+    /// doesn't receive at least `min_out` after fees. Returns the amounts paid and received.
+    fn swap_exact_in(
+        env: Env,
+        trader: Address,
+        side: OrderSide,
+        amount_in: i128,
+        min_out: i128,
+    ) -> Result<(i128, i128), DexMarketError> {
+        let market_info = market_info(&env)?;
+
+        if amount_in <= 0 || min_out < 0 {
+            return Err(DexMarketError::InvalidAmount);
         }
 
-        let mut quote_consumed = 0;
-        let mut base_consumed = 0;
-        let mut is_self_trade = false;
-        let summary = order_book.place_order(&params, |entry| {
-            is_self_trade = is_self_trade || entry.details.owner == params.details.owner;
+        check_fill_order(&env, &trader)?;
 
-            let base_amount = entry.size as i128;
-            let quote_amount = quote_amount(entry.price, entry.size);
+        let order_book = order_book_state(&env);
+        let plan =
+            swap::FillPlan::new(&order_book, &market_info, side.into(), amount_in, i128::MAX)?;
 
-            base_consumed += base_amount;
-            quote_consumed += quote_amount;
+        if plan.size == 0 {
+            return Err(DexMarketError::InsufficientLiquidity);
+        }
 
-            match entry.id.side() {
-                OrderSide::Bid => {
-                    base.transfer(
-                        &env.current_contract_address(),
-                        &entry.details.owner,
-                        &base_amount,
-                    );
+        let summary = execute_fill(&env, &market_info, &order_book, trader, side, &plan)?;
+        let amount_out = summary.amount_out(side);
 
-                    quote.transfer(
-                        &env.current_contract_address(),
-                        &params.details.owner,
-                        &quote_amount,
-                    );
-                }
+        if amount_out < min_out {
+            return Err(DexMarketError::SlippageExceeded);
+        }
 
-                OrderSide::Ask => {
-                    quote.transfer(
-                        &env.current_contract_address(),
-                        &entry.details.owner,
-                        &quote_amount,
-                    );
-
-                    base.transfer(
-                        &env.current_contract_address(),
-                        &params.details.owner,
-                        &base_amount,
-                    );
-                }
-            }
+        Ok((summary.amount_in(side), amount_out))
+    }
+
+    /// Swap at most `max_in` of the token paid for an order, to receive `amount_out` after fees
+    ///
+    /// The fills are sized so that at least `amount_out` is left once fees, which are rounded up,
+    /// have been taken, so slightly more than `amount_out` can be received. Nothing is posted to
+    /// the book. Returns the amount paid.
+    fn swap_exact_out(
+        env: Env,
+        trader: Address,
+        side: OrderSide,
+        amount_out: i128,
+        max_in: i128,
+    ) -> Result<i128, DexMarketError> {
+        let market_info = market_info(&env)?;
 
-            // Consume the maker side events too, since we already transferred their tokens
-            //
-            // Ideally the events would be consumed separately to avoid conflicts in tx footprints
+        if amount_out <= 0 || max_in < 0 {
+            return Err(DexMarketError::InvalidAmount);
+        }
 
-            let mut orders_to_consume = Map::new(&env);
-            orders_to_consume.set(entry.id.clone(), 1);
+        check_fill_order(&env, &trader)?;
 
-            order_book.consume_events(orders_to_consume);
-        });
+        let order_book = order_book_state(&env);
+        let plan = swap::FillPlan::new(
+            &order_book,
+            &market_info,
+            side.into(),
+            i128::MAX,
+            amount_out,
+        )?;
 
-        if is_self_trade {
-            return Err(DexMarketError::CannotSelfTrade);
+        if plan.amount_out < amount_out {
+            return Err(DexMarketError::InsufficientLiquidity);
         }
 
-        // return unnecessary tokens
-        match params.side {
-            OrderSide::Bid => {
-                let return_token_amount = quote_offer_amount
-                    - quote_consumed
-                    - quote_amount(params.price, summary.posted_size);
-
-                quote.transfer(
-                    &env.current_contract_address(),
-                    &params.details.owner,
-                    &return_token_amount,
-                );
-            }
-
-            OrderSide::Ask => {
-                let return_token_amount =
-                    (params.size - summary.posted_size) as i128 - base_consumed;
-
-                base.transfer(
-                    &env.current_contract_address(),
-                    &params.details.owner,
-                    &return_token_amount,
-                );
-            }
+        if plan.amount_in > max_in {
+            return Err(DexMarketError::SlippageExceeded);
         }
 
-        Ok(summary.posted_id)
+        let summary = execute_fill(&env, &market_info, &order_book, trader, side, &plan)?;
+
+        Ok(summary.amount_in(side))
     }
 
     /// Cancel a previously placed order
-    fn cancel_order(env: Env, order: OrderId) {
-        use orderbook::OrderSide;
+    ///
+    /// Fails with [DexMarketError::OrderNotFound] if the order is no longer on the book.
+    fn cancel_order(env: Env, order: OrderId) -> Result<(), DexMarketError> {
+        remove_order(&env, order, |owner| {
+            owner.require_auth();
 
-        let order_book = order_book_state(&env);
-        let order_detail = order_book.get_order(&order);
-
-        if let Some(order_detail) = order_detail {
-            order_detail.details.owner.require_auth();
-
-            let market_info: DexMarketInfo = env.storage().instance().get(&MARKET_INFO).unwrap();
-            let base = token::Client::new(&env, &market_info.base_token);
-            let quote = token::Client::new(&env, &market_info.quote_token);
-
-            match order_detail.id.side() {
-                OrderSide::Ask => {
-                    base.transfer(
-                        &env.current_contract_address(),
-                        &order_detail.details.owner,
-                        &(order_detail.size as i128),
-                    );
-                }
+            Ok(())
+        })
+    }
 
-                OrderSide::Bid => {
-                    let token_amount = quote_amount(order_detail.price, order_detail.size);
+    /// Cancel an order for its owner, as one of their delegates
+    fn cancel_order_as(env: Env, delegate: Address, order: OrderId) -> Result<(), DexMarketError> {
+        remove_order(&env, order, |owner| {
+            delegates::require_delegate(&env, owner, &delegate, DelegateAction::Cancel)?;
 
-                    quote.transfer(
-                        &env.current_contract_address(),
-                        &order_detail.details.owner,
-                        &token_amount,
-                    );
-                }
-            }
+            Ok(())
+        })
+    }
 
-            order_book.cancel_order(&order);
-        }
+    /// Cancel up to `max_orders` of an owner's orders, returning the number cancelled
+    ///
+    /// Only the owner's own orders are read, oldest first, and at most `max_orders` of them, so
+    /// the cost of a call doesn't depend on the size of the book or on how many orders the
+    /// owner has.
+    fn cancel_all_orders(env: Env, owner: Address, max_orders: u32) -> Result<u32, DexMarketError> {
+        owner.require_auth();
+
+        remove_owner_orders(&env, &owner, max_orders)
     }
-}
 
-fn order_book_state(env: &Env) -> OrderBook<OrderDetail> {
-    OrderBook::open(&env, 0xF1A0)
-}
+    /// Cancel up to `max_orders` of an owner's orders as one of their delegates, returning the
+    /// number cancelled
+    fn cancel_all_orders_as(
+        env: Env,
+        delegate: Address,
+        owner: Address,
+        max_orders: u32,
+    ) -> Result<u32, DexMarketError> {
+        delegates::require_delegate(&env, &owner, &delegate, DelegateAction::CancelAll)?;
 
-#[contracttype]
-struct OrderDetail {
-    owner: Address,
-}
+        remove_owner_orders(&env, &owner, max_orders)
+    }
 
-const MARKET_INFO: Symbol = symbol_short!("MARKETINF");
+    /// Allow a delegate to manage an owner's orders, replacing what it was allowed before
+    fn grant_delegate(
+        env: Env,
+        owner: Address,
+        delegate: Address,
+        permissions: DelegatePermissions,
+    ) -> Result<(), DexMarketError> {
+        market_info(&env)?;
+        owner.require_auth();
 
-fn quote_amount(price: u64, base_amount: u128) -> i128 {
-    let price = U96F32::from_bits(price as u128);
-    let token_amount = price * U96F32::from_num(base_amount);
+        delegates::grant(&env, &owner, &delegate, &permissions)?;
+        events::delegate_granted(&env, &owner, &delegate, &permissions);
 
-    token_amount.to_num()
-}
+        Ok(())
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Remove everything a delegate was allowed to do for an owner
+    fn revoke_delegate(env: Env, owner: Address, delegate: Address) -> Result<(), DexMarketError> {
+        market_info(&env)?;
+        owner.require_auth();
 
-    struct TestEnv {
+        delegates::revoke(&env, &owner, &delegate);
+        events::delegate_revoked(&env, &owner, &delegate);
+
+        Ok(())
+    }
+
+    /// What a delegate is allowed to do for an owner, unless it has expired
+    fn delegate_permissions(
         env: Env,
-        base_token: Address,
-        quote_token: Address,
-        users: std::vec::Vec<Address>,
-        market: Address,
+        owner: Address,
+        delegate: Address,
+    ) -> Option<DelegatePermissions> {
+        delegates::permissions(&env, &owner, &delegate)
     }
 
-    impl TestEnv {
-        fn new() -> Self {
-            use soroban_sdk::testutils::Address;
+    /// The configuration of the market
+    fn market_info(env: Env) -> Result<DexMarketInfo, DexMarketError> {
+        market_info(&env)
+    }
 
-            let env = Env::default();
-            let base_token = env.register_contract(None, test_token::Token);
-            let quote_token = env.register_contract(None, test_token::Token);
-            let market = env.register_contract(None, DexMarketContract);
+    /// Look up an order which is resting in the book
+    fn get_order(env: Env, order: OrderId) -> Result<OrderInfo, DexMarketError> {
+        let order_book = order_book_state(&env);
+        order_book
+            .check_id(&order)
+            .map_err(|_| DexMarketError::InvalidOrderId)?;
 
-            let market_client = DexMarketContractClient::new(&env, &market);
-            market_client.init(&DexMarketInfo {
-                base_token: base_token.clone(),
-                quote_token: quote_token.clone(),
-                base_min_order_size: 1,
-            });
+        let entry = order_book
+            .get_order(&order)
+            .ok_or(DexMarketError::OrderNotFound)?;
 
-            let users = vec![
-                soroban_sdk::Address::random(&env),
-                soroban_sdk::Address::random(&env),
-            ];
+        Ok(OrderInfo {
+            pending_events: order_book.order_events(&order),
+            side: order.side().into(),
+            id: order,
+            owner: entry.details.owner,
+            funding: entry.details.funding,
+            price: entry.price,
+            remaining_size: entry.size,
+        })
+    }
 
-            Self {
-                env,
-                base_token,
-                quote_token,
-                market,
-                users,
-            }
-        }
+    /// The ids of up to `max_orders` of an owner's orders resting in the book, oldest first
+    ///
+    /// The list starts after the order `after`, or with the oldest order if it's `None`, so it
+    /// can be read a page at a time.
+    fn owner_orders(
+        env: Env,
+        owner: Address,
+        after: Option<OrderId>,
+        max_orders: u32,
+    ) -> Vec<OrderId> {
+        owner_orders::orders(&env, &owner, after, max_orders)
+    }
 
-        fn market_client(&self) -> DexMarketContractClient {
-            DexMarketContractClient::new(&self.env, &self.market)
-        }
+    /// The prices of the best bid and the best ask in the book (U32F32 format)
+    fn best_bid_ask(env: Env) -> (Option<u64>, Option<u64>) {
+        let order_book = order_book_state(&env);
 
-        fn base_client(&self) -> test_token::TokenClient {
-            test_token::TokenClient::new(&self.env, &self.base_token)
-        }
+        (
+            best_price(&order_book, orderbook::OrderSide::Bid),
+            best_price(&order_book, orderbook::OrderSide::Ask),
+        )
+    }
 
-        fn quote_client(&self) -> test_token::TokenClient {
-            test_token::TokenClient::new(&self.env, &self.quote_token)
+    /// The difference between the best ask and the best bid (U32F32 format)
+    ///
+    /// There is no spread unless both sides of the book have orders.
+    fn spread(env: Env) -> Option<u64> {
+        match Self::best_bid_ask(env) {
+            (Some(bid), Some(ask)) => Some(ask - bid),
+            _ => None,
         }
     }
 
-    #[test]
-    fn test_simple_swap() {
-        let ctx = TestEnv::new();
+    /// The price halfway between the best bid and the best ask, rounded down (U32F32 format)
+    ///
+    /// There is no mid price unless both sides of the book have orders.
+    fn mid_price(env: Env) -> Option<u64> {
+        mid_price(&order_book_state(&env))
+    }
 
-        let market = ctx.market_client();
+    /// Withdraw all fees collected by the market
+    fn withdraw_fees(env: Env, to: Address) -> Result<(), DexMarketError> {
+        let market_info = market_info(&env)?;
+        market_info.fee_authority.require_auth();
 
-        ctx.env.mock_all_auths();
+        transfer_all(&env, fees::take_accrued(&env), &to);
 
-        ctx.base_client().mint(&ctx.users[0], &125);
-        ctx.quote_client().mint(&ctx.users[1], &100);
+        Ok(())
+    }
 
-        let _ = market
-            .place_order(&OrderParams {
-                side: OrderSide::Ask,
-                size: 125,
-                price: (1 << 32),
-                owner: ctx.users[0].clone(),
-            })
-            .unwrap();
+    /// The tokens kept by the market from rounding amounts in its favour, per token
+    fn dust(env: Env) -> Map<Address, i128> {
+        fees::dust(&env)
+    }
 
-        market.place_order(&OrderParams {
-            side: OrderSide::Bid,
-            size: 100,
-            price: (1 << 32),
-            owner: ctx.users[1].clone(),
-        });
+    /// Transfer the rounding dust kept by the market to an address
+    ///
+    /// Like the fees, the dust can only be swept by the fee authority.
+    fn sweep_dust(env: Env, to: Address) -> Result<(), DexMarketError> {
+        let market_info = market_info(&env)?;
+        market_info.fee_authority.require_auth();
 
-        let balance_0_quote = ctx.quote_client().balance(&ctx.users[0]);
-        let balance_1_base = ctx.base_client().balance(&ctx.users[1]);
+        transfer_all(&env, fees::take_dust(&env), &to);
 
-        assert_eq!(100, balance_0_quote);
-        assert_eq!(100, balance_1_base);
+        Ok(())
     }
 
-    #[test]
-    fn test_price_limit_matching() {
-        let ctx = TestEnv::new();
+    /// Change the fees charged by the market, which applies to all future fills
+    fn set_fee_schedule(
+        env: Env,
+        maker_fee_bps: i32,
+        taker_fee_bps: u32,
+    ) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
 
-        let market = ctx.market_client();
+        let mut market_info = market_info(&env)?;
+        market_info.maker_fee_bps = maker_fee_bps;
+        market_info.taker_fee_bps = taker_fee_bps;
 
-        ctx.env.mock_all_auths();
+        fees::validate_schedule(&market_info)?;
+        set_market_info(&env, &market_info);
 
-        ctx.base_client().mint(&ctx.users[0], &1_000);
-        ctx.quote_client().mint(&ctx.users[1], &3_000);
+        Ok(())
+    }
 
-        let _ = market
-            .place_order(&OrderParams {
-                side: OrderSide::Ask,
-                size: 1_000,
-                price: (2 << 32),
-                owner: ctx.users[0].clone(),
-            })
-            .unwrap();
+    /// Change the address allowed to withdraw fees
+    fn set_fee_authority(env: Env, fee_authority: Address) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
 
-        market.place_order(&OrderParams {
-            side: OrderSide::Bid,
-            size: 1_000,
-            price: (3 << 32),
-            owner: ctx.users[1].clone(),
-        });
+        let mut market_info = market_info(&env)?;
+        market_info.fee_authority = fee_authority;
+        set_market_info(&env, &market_info);
 
-        let balance_0_quote = ctx.quote_client().balance(&ctx.users[0]);
-        let balance_1_base = ctx.base_client().balance(&ctx.users[1]);
+        Ok(())
+    }
 
-        let balance_0_base = ctx.base_client().balance(&ctx.users[0]);
-        let balance_1_quote = ctx.quote_client().balance(&ctx.users[1]);
+    /// Change the minimum size for new orders
+    fn set_min_order_size(env: Env, base_min_order_size: u128) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
 
-        assert_eq!(0, balance_0_base);
-        assert_eq!(2_000, balance_0_quote);
+        let mut market_info = market_info(&env)?;
+        market_info.base_min_order_size = base_min_order_size;
+        set_market_info(&env, &market_info);
+
+        Ok(())
+    }
+
+    /// Propose a new admin for the market
+    ///
+    /// The new admin only takes over once they call `accept_admin`.
+    fn set_admin(env: Env, new_admin: Address) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+        admin::set_pending_admin(&env, &new_admin);
+
+        Ok(())
+    }
+
+    /// Accept the admin role, after being proposed by the current admin
+    fn accept_admin(env: Env) -> Result<(), DexMarketError> {
+        let new_admin = admin::take_pending_admin(&env).ok_or(DexMarketError::NoPendingAdmin)?;
+        new_admin.require_auth();
+
+        admin::set_admin(&env, &new_admin);
+
+        Ok(())
+    }
+
+    /// Change the trading status of the market
+    ///
+    /// Markets that are being closed can't be reopened, and can only be closed through
+    /// `close_market`.
+    fn set_status(env: Env, status: MarketStatus) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+        status::market_status(&env).check_open()?;
+
+        if let MarketStatus::Closing | MarketStatus::Closed = status {
+            return Err(DexMarketError::MarketNotClosing);
+        }
+
+        rewards::accrue(&env)?;
+        status::set_market_status(&env, status);
+
+        Ok(())
+    }
+
+    /// The current trading status of the market
+    fn status(env: Env) -> MarketStatus {
+        status::market_status(&env)
+    }
+
+    /// Replace the code of the market with a wasm that has already been uploaded
+    ///
+    /// The new code takes effect from the next call. If the storage layout changed, the market
+    /// can't be used until it's been migrated with `migrate` and `migrate_orders`, and the admin
+    /// upgrading it is the one who has to authorize `migrate`.
+    fn upgrade(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), DexMarketError> {
+        let admin = admin::require_admin(&env)?;
+        migration::set_migrator(&env, &admin);
+
+        env.deployer()
+            .update_current_contract_wasm(new_wasm_hash.clone());
+        events::upgraded(&env, &new_wasm_hash);
+
+        Ok(())
+    }
+
+    /// The version of the storage layout of the market
+    fn schema_version(env: Env) -> u32 {
+        migration::schema_version(&env)
+    }
+
+    /// Migrate the configuration of the market to the layout used by the current code
+    ///
+    /// Only the admin who upgraded the market can do this, and fails with `Unauthorized` if
+    /// nobody did. Markets from before admins existed have none, so that admin becomes their
+    /// admin and fee authority. Trading stays blocked until `migrate_orders` has moved the
+    /// resting orders. Returns true if anything was migrated.
+    fn migrate(env: Env) -> Result<bool, DexMarketError> {
+        migration::migrate(&env)
+    }
+
+    /// Move up to `max_orders` resting orders to the book of the current layout
+    ///
+    /// Anyone can call this, as many times as it takes to move every order, once `migrate` has
+    /// been called. Orders which can't be carried over exactly are refunded to their owners.
+    /// Returns true once the market is fully migrated.
+    fn migrate_orders(env: Env, max_orders: u32) -> Result<bool, DexMarketError> {
+        migration::migrate_orders(&env, max_orders)
+    }
+
+    /// Start winding down the market
+    ///
+    /// No new orders can be placed after this, and all resting orders are refunded to
+    /// their owners by `settle_closing`.
+    fn close_market(env: Env) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+        status::market_status(&env).check_open()?;
+
+        rewards::accrue(&env)?;
+        status::set_market_status(&env, MarketStatus::Closing);
+
+        Ok(())
+    }
+
+    /// Refund up to `max_orders` resting orders in a closing market
+    ///
+    /// Anyone can call this, as many times as it takes to empty the book. Each call reads at most
+    /// `max_orders` orders, from the best prices on each side. Returns true once the book is
+    /// empty and the market has been marked as closed.
+    fn settle_closing(env: Env, max_orders: u32) -> Result<bool, DexMarketError> {
+        use orderbook::OrderSide;
+
+        let market_info = market_info(&env)?;
+
+        if status::market_status(&env) != MarketStatus::Closing {
+            return Err(DexMarketError::MarketNotClosing);
+        }
+
+        let order_book = order_book_state(&env);
+        let mut orders = Vec::new(&env);
+
+        // the book is read lazily from the best price, so no more orders are read than refunded
+        for side in [OrderSide::Bid, OrderSide::Ask] {
+            let remaining = max_orders - orders.len();
+
+            for order in order_book.orders(side).into_iter().take(remaining as usize) {
+                orders.push_back(order);
+            }
+        }
+
+        rewards::accrue(&env)?;
+
+        for order in orders.iter() {
+            let Some(entry) = order_book.get_order(&order) else {
+                continue;
+            };
+
+            order_book
+                .cancel_order(&order)
+                .map_err(|_| DexMarketError::InvalidOrderId)?;
+
+            refund_order(&env, &market_info, &entry)?;
+        }
+
+        let is_empty = [OrderSide::Bid, OrderSide::Ask]
+            .into_iter()
+            .all(|side| order_book.orders(side).into_iter().next().is_none());
+
+        if is_empty {
+            status::set_market_status(&env, MarketStatus::Closed);
+        }
+
+        Ok(is_empty)
+    }
+
+    /// Move tokens into an owner's internal balance, to fund orders placed with
+    /// `Funding::Vault`
+    fn deposit(
+        env: Env,
+        owner: Address,
+        base_amount: i128,
+        quote_amount: i128,
+    ) -> Result<(), DexMarketError> {
+        let market_info = market_info(&env)?;
+        status::market_status(&env).check_open()?;
+
+        if base_amount < 0 || quote_amount < 0 {
+            return Err(DexMarketError::InvalidAmount);
+        }
+
+        owner.require_auth();
+
+        let funds = Funds::new(&env, &market_info);
+        funds.deposit(&owner, Asset::Base, base_amount)?;
+        funds.deposit(&owner, Asset::Quote, quote_amount)
+    }
+
+    /// Move tokens from an owner's free internal balance back to their account
+    fn withdraw(
+        env: Env,
+        owner: Address,
+        base_amount: i128,
+        quote_amount: i128,
+    ) -> Result<(), DexMarketError> {
+        let market_info = market_info(&env)?;
+
+        if base_amount < 0 || quote_amount < 0 {
+            return Err(DexMarketError::InvalidAmount);
+        }
+
+        owner.require_auth();
+
+        let funds = Funds::new(&env, &market_info);
+        funds.withdraw(&owner, Asset::Base, base_amount)?;
+        funds.withdraw(&owner, Asset::Quote, quote_amount)?;
+
+        Ok(())
+    }
+
+    /// The internal balance held by an owner
+    fn balance(env: Env, owner: Address) -> VaultBalance {
+        funds::vault_balance(&env, &owner)
+    }
+
+    /// Transfer the tokens owed to an owner from fills that could not be paid out to them
+    fn claim(env: Env, owner: Address) -> Result<UnsettledFunds, DexMarketError> {
+        let market_info = market_info(&env)?;
+        owner.require_auth();
+
+        Funds::new(&env, &market_info).claim(&owner)
+    }
+
+    /// The tokens owed to an owner from fills that could not be paid out to them
+    fn unsettled(env: Env, owner: Address) -> UnsettledFunds {
+        funds::unsettled_funds(&env, &owner)
+    }
+
+    /// Change the share of the taker fee paid to the referrers of orders, in basis points
+    ///
+    /// The share is taken from what's left of the taker fee after maker rebates, and applies
+    /// to all future fills.
+    fn set_referral_share(env: Env, share_bps: u32) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+
+        referrals::set_referral_share(&env, share_bps)
+    }
+
+    /// The share of the taker fee paid to the referrers of orders, in basis points
+    fn referral_share(env: Env) -> u32 {
+        referrals::referral_share(&env)
+    }
+
+    /// Transfer all the fees a referrer has earned to them, returning the amounts transferred
+    fn claim_referral_fees(
+        env: Env,
+        referrer: Address,
+    ) -> Result<ReferralEarnings, DexMarketError> {
+        let market_info = market_info(&env)?;
+        referrer.require_auth();
+
+        let earnings = referrals::take_earnings(&env, &referrer);
+        let funds = Funds::new(&env, &market_info);
+
+        funds.pay(&referrer, Funding::Wallet, Asset::Base, earnings.base)?;
+        funds.pay(&referrer, Funding::Wallet, Asset::Quote, earnings.quote)?;
+
+        Ok(earnings)
+    }
+
+    /// The fees a referrer has earned which have not been claimed yet
+    fn referral_earnings(env: Env, referrer: Address) -> ReferralEarnings {
+        referrals::earnings(&env, &referrer)
+    }
+
+    /// Change how makers are rewarded for resting liquidity close to the mid price
+    ///
+    /// Rewards earned until now are accrued with the previous configuration first.
+    fn set_reward_config(env: Env, config: RewardConfig) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+
+        rewards::set_config(&env, &config)
+    }
+
+    /// How makers are rewarded for resting liquidity, if they are
+    fn reward_config(env: Env) -> Option<RewardConfig> {
+        rewards::config(&env)
+    }
+
+    /// Transfer reward tokens into the market, to be emitted as liquidity rewards
+    ///
+    /// Anyone can fund rewards, and the tokens can't be withdrawn other than as rewards.
+    fn fund_rewards(env: Env, from: Address, amount: i128) -> Result<(), DexMarketError> {
+        from.require_auth();
+
+        rewards::fund(&env, &from, amount)
+    }
+
+    /// The funded reward tokens which have not been emitted yet
+    fn reward_pool(env: Env) -> i128 {
+        rewards::pool(&env)
+    }
+
+    /// Transfer all the liquidity rewards an owner has earned to them, returning the amount
+    /// transferred
+    fn claim_rewards(env: Env, owner: Address) -> Result<i128, DexMarketError> {
+        owner.require_auth();
+
+        rewards::claim(&env, &owner)
+    }
+
+    /// The liquidity rewards an owner has earned but not claimed
+    ///
+    /// Rewards are accrued whenever the book changes, so this doesn't include the time since
+    /// the last change. Claiming pays out everything earned until then.
+    fn rewards(env: Env, owner: Address) -> i128 {
+        rewards::earned(&env, &owner)
+    }
+
+    /// Change the intervals candles are recorded for, in seconds
+    ///
+    /// Candles for intervals which are removed are discarded.
+    fn set_candle_intervals(env: Env, intervals: Vec<u64>) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+        history::set_candle_intervals(&env, &intervals)?;
+
+        events::candle_intervals_changed(&env, &intervals);
+
+        Ok(())
+    }
+
+    /// The intervals candles are recorded for, in seconds
+    fn candle_intervals(env: Env) -> Vec<u64> {
+        history::candle_intervals(&env)
+    }
+
+    /// The most recent candles recorded for an interval, oldest first
+    ///
+    /// Only intervals with trades have a candle, and at most 64 candles are kept per interval.
+    fn candles(env: Env, interval: u64, limit: u32) -> Vec<Candle> {
+        let candles = history::candles(&env, interval);
+        let start = candles.len().saturating_sub(limit);
+
+        candles.slice(start..)
+    }
+
+    /// The price and time of the most recent trade, if there has been any
+    fn last_trade(env: Env) -> Option<LastTrade> {
+        history::last_trade(&env)
+    }
+
+    /// The volume traded in roughly the last 24 hours, tracked in hourly buckets
+    fn volume_24h(env: Env) -> Volume {
+        history::volume_24h(&env)
+    }
+
+    /// Change the length of the periods the oracle averages prices over, in seconds
+    ///
+    /// This discards the price history recorded so far.
+    fn set_oracle_resolution(env: Env, resolution: u32) -> Result<(), DexMarketError> {
+        admin::require_admin(&env)?;
+        oracle::set_resolution(&env, resolution)?;
+
+        events::oracle_resolution_changed(&env, resolution);
+
+        Ok(())
+    }
+
+    /// The asset oracle prices are quoted in, which is the quote token
+    fn base(env: Env) -> Result<Address, DexMarketError> {
+        Ok(market_info(&env)?.quote_token)
+    }
+
+    /// The assets the oracle has prices for, which is only the base token
+    fn assets(env: Env) -> Result<Vec<Address>, DexMarketError> {
+        Ok(Vec::from_array(&env, [market_info(&env)?.base_token]))
+    }
+
+    /// The number of decimals in oracle prices
+    fn decimals(_env: Env) -> u32 {
+        oracle::DECIMALS
+    }
+
+    /// The length of the periods the oracle averages prices over, in seconds
+    fn resolution(env: Env) -> u32 {
+        oracle::resolution(&env)
+    }
+
+    /// The average price over the period ending at a timestamp, rounded down to a period boundary
+    fn price(env: Env, asset: Address, timestamp: u64) -> Option<PriceData> {
+        check_oracle_asset(&env, &asset)?;
+        oracle::average_price(&env, timestamp, 1)
+    }
+
+    /// The average price over the last complete period
+    fn lastprice(env: Env, asset: Address) -> Option<PriceData> {
+        check_oracle_asset(&env, &asset)?;
+        oracle::average_price(&env, env.ledger().timestamp(), 1)
+    }
+
+    /// The average prices over up to `records` of the most recent complete periods, newest first
+    fn prices(env: Env, asset: Address, records: u32) -> Option<Vec<PriceData>> {
+        check_oracle_asset(&env, &asset)?;
+
+        let resolution = oracle::resolution(&env) as u64;
+        let mut end = env.ledger().timestamp();
+        let mut prices = Vec::new(&env);
+
+        for _ in 0..records {
+            let Some(price) = oracle::average_price(&env, end, 1) else {
+                break;
+            };
+
+            end = price.timestamp.saturating_sub(resolution);
+            prices.push_back(price);
+        }
+
+        match prices.is_empty() {
+            true => None,
+            false => Some(prices),
+        }
+    }
+
+    /// The average price over the last `periods` complete periods
+    fn twap(env: Env, asset: Address, periods: u32) -> Option<PriceData> {
+        check_oracle_asset(&env, &asset)?;
+        oracle::average_price(&env, env.ledger().timestamp(), periods)
+    }
+}
+
+/// Match an order against the book, and settle the tokens for it
+///
+/// `offer_amount` of the token the order pays with is collected from the owner up front,
+/// and anything which isn't needed for fills or for the posted part of the order is
+/// returned. If `post` is false, the unfilled part of the order is discarded instead of
+/// being posted to the book. The referrer of the order, if any, earns its share of the taker
+/// fees.
+#[allow(clippy::too_many_arguments)]
+fn execute_order(
+    env: &Env,
+    market_info: &DexMarketInfo,
+    order_book: &OrderBook<OrderDetail>,
+    params: &orderbook::OrderParams<OrderDetail>,
+    referrer: Option<&Address>,
+    offer_amount: i128,
+    post: bool,
+) -> Result<(Option<OrderId>, FillSummary), DexMarketError> {
+    use orderbook::OrderSide;
+
+    rewards::accrue(env)?;
+
+    let funds = Funds::new(env, market_info);
+    let owner = &params.details.owner;
+    let funding = params.details.funding;
+
+    // the asset the order pays with
+    let offer_asset = match params.side {
+        OrderSide::Bid => Asset::Quote,
+        OrderSide::Ask => Asset::Base,
+    };
+
+    funds.collect(owner, funding, offer_asset, offer_amount)?;
+
+    let mut quote_paid = 0;
+    let mut quote_dust = 0;
+    let mut taker_value = QuoteValue::default();
+    let mut base_consumed = 0;
+    let mut taker_proceeds = 0;
+    let mut taker_fee = 0;
+    let mut taker_net_fees = 0;
+    let mut base_fees = 0;
+    let mut quote_fees = 0;
+    let mut is_self_trade = false;
+    let mut trade_stats = history::TradeStats::default();
+    let mut fill = |entry: &OrderEntry<OrderId, OrderDetail>| -> Result<(), DexMarketError> {
+        is_self_trade = is_self_trade || entry.details.owner == *owner;
+
+        let base_amount = price::to_amount(entry.size)?;
+        let fill_value = QuoteValue::new(entry.price, entry.size)?;
+        let quote_amount = fill_value.round_down(market_info)?;
+
+        base_consumed = add_amounts(base_consumed, base_amount)?;
+        quote_paid = add_amounts(quote_paid, quote_amount)?;
+        taker_value = taker_value.add(fill_value)?;
+        trade_stats.add(entry.price, entry.size, quote_amount);
+
+        // the maker receives the opposite of what the taker receives
+        let (maker_asset, taker_asset, maker_amount, taker_amount) = match entry.id.side() {
+            OrderSide::Bid => (Asset::Base, Asset::Quote, base_amount, quote_amount),
+            OrderSide::Ask => (Asset::Quote, Asset::Base, quote_amount, base_amount),
+        };
+
+        let fill_fees = FillFees::new(market_info, maker_amount, taker_amount)?;
+        let maker = &entry.details.owner;
+        let maker_funding = entry.details.funding;
+
+        // the maker's escrow pays for what the taker receives
+        let escrow_used = match entry.id.side() {
+            OrderSide::Bid => {
+                let remaining = order_book
+                    .get_order(&entry.id)
+                    .map_or(0, |order| order.size);
+                let escrow_used =
+                    price::bid_escrow(market_info, entry.price, remaining + entry.size)?
+                        - price::bid_escrow(market_info, entry.price, remaining)?;
+
+                quote_dust = add_amounts(quote_dust, escrow_used - quote_amount)?;
+                escrow_used
+            }
+
+            OrderSide::Ask => base_amount,
+        };
+
+        funds.spend_locked(maker, maker_funding, taker_asset, escrow_used)?;
+        funds.settle(
+            maker,
+            maker_funding,
+            maker_asset,
+            maker_amount - fill_fees.maker_fee,
+        )?;
+        funds.settle(maker, maker_funding, taker_asset, fill_fees.maker_rebate)?;
+
+        taker_proceeds = add_amounts(taker_proceeds, taker_amount - fill_fees.taker_fee)?;
+        taker_fee = add_amounts(taker_fee, fill_fees.taker_fee)?;
+
+        let taker_token_fees = fill_fees.taker_fee - fill_fees.maker_rebate;
+        taker_net_fees = add_amounts(taker_net_fees, taker_token_fees)?;
+
+        match entry.id.side() {
+            OrderSide::Bid => {
+                base_fees = add_amounts(base_fees, fill_fees.maker_fee)?;
+                quote_fees = add_amounts(quote_fees, taker_token_fees)?;
+            }
+
+            OrderSide::Ask => {
+                quote_fees = add_amounts(quote_fees, fill_fees.maker_fee)?;
+                base_fees = add_amounts(base_fees, taker_token_fees)?;
+            }
+        }
+
+        // Consume the maker side events too, since we already transferred their tokens
+        //
+        // Ideally the events would be consumed separately to avoid conflicts in tx footprints
+
+        let mut orders_to_consume = Map::new(env);
+        orders_to_consume.set(entry.id.clone(), 1);
+
+        order_book.consume_events(orders_to_consume);
+
+        events::trade(
+            env,
+            maker,
+            owner,
+            TradeEvent {
+                maker_order_id: entry.id.clone(),
+                maker_side: entry.id.side().into(),
+                price: entry.price,
+                size: entry.size,
+                quote_amount,
+                taker_fee: fill_fees.taker_fee,
+                maker_fee: fill_fees.maker_fee,
+                maker_rebate: fill_fees.maker_rebate,
+            },
+        );
+
+        if order_book.get_order(&entry.id).is_none() {
+            owner_orders::remove(env, maker, &entry.id);
+            events::order_filled(env, maker, &entry.id);
+        }
+
+        Ok(())
+    };
+
+    // the book can't stop matching part way, so keep the first error and fail with it afterwards
+    let mut fill_error = None;
+    let on_match = |entry: &OrderEntry<OrderId, OrderDetail>| {
+        if fill_error.is_none() {
+            fill_error = fill(entry).err();
+        }
+    };
+
+    let summary = match post {
+        true => order_book
+            .place_order(params, on_match)
+            .map_err(|_| DexMarketError::InvalidPrice)?,
+        false => {
+            order_book.fill_order(params, on_match);
+
+            OrderSummary {
+                posted_id: None,
+                posted_size: 0,
+            }
+        }
+    };
+
+    if let Some(error) = fill_error {
+        return Err(error);
+    }
+
+    if is_self_trade {
+        return Err(DexMarketError::CannotSelfTrade);
+    }
+
+    history::record(env, &trade_stats);
+
+    if let Some(price) = trade_stats.last_price() {
+        oracle::record(env, price);
+    }
+
+    // a taker bid pays for all of its fills at once, rounded up
+    let (proceeds_asset, posted_amount, consumed_amount, quote_filled) = match params.side {
+        OrderSide::Bid => {
+            let quote_consumed = taker_value.round_up(market_info)?;
+            quote_dust = add_amounts(quote_dust, quote_consumed - quote_paid)?;
+
+            (
+                Asset::Base,
+                price::bid_escrow(market_info, params.price, summary.posted_size)?,
+                quote_consumed,
+                quote_consumed,
+            )
+        }
+
+        OrderSide::Ask => (
+            Asset::Quote,
+            summary.posted_size as i128,
+            base_consumed,
+            quote_paid,
+        ),
+    };
+
+    // the referrer's share comes out of the fees taken in the token the taker receives
+    if let Some(referrer) = referrer {
+        let referral_fee = referrals::referral_fee(env, taker_net_fees)?;
+
+        match proceeds_asset {
+            Asset::Base => base_fees = add_amounts(base_fees, -referral_fee)?,
+            Asset::Quote => quote_fees = add_amounts(quote_fees, -referral_fee)?,
+        }
+
+        referrals::credit(env, referrer, proceeds_asset, referral_fee)?;
+    }
+
+    fees::accrue(env, &market_info.base_token, base_fees)?;
+    fees::accrue(env, &market_info.quote_token, quote_fees)?;
+    fees::accrue_dust(env, &market_info.quote_token, quote_dust)?;
+
+    if let Some(posted_id) = &summary.posted_id {
+        owner_orders::add(env, owner, posted_id);
+        events::order_placed(
+            env,
+            owner,
+            OrderPlacedEvent {
+                order_id: posted_id.clone(),
+                side: params.side.into(),
+                price: params.price,
+                size: params.size,
+                posted_size: summary.posted_size,
+            },
+        );
+    }
+
+    // hold the tokens for the posted order, and settle the difference with what was collected
+    let change = offer_amount - consumed_amount - posted_amount;
+
+    if change < 0 {
+        funds.collect(owner, funding, offer_asset, -change)?;
+    }
+
+    funds.pay(owner, funding, proceeds_asset, taker_proceeds)?;
+    funds.lock(owner, funding, offer_asset, posted_amount)?;
+    funds.pay(owner, funding, offer_asset, change.max(0))?;
+
+    Ok((
+        summary.posted_id,
+        FillSummary {
+            posted_size: summary.posted_size,
+            base_filled: base_consumed as u128,
+            quote_filled,
+            taker_fee,
+            base_fees,
+            quote_fees,
+        },
+    ))
+}
+
+/// Cancel an order, once `authorize` has accepted its owner
+fn remove_order(
+    env: &Env,
+    order: OrderId,
+    authorize: impl FnOnce(&Address) -> Result<(), DexMarketError>,
+) -> Result<(), DexMarketError> {
+    let market_info = market_info(env)?;
+    status::market_status(env).check_cancel_order()?;
+
+    let order_book = order_book_state(env);
+    order_book
+        .check_id(&order)
+        .map_err(|_| DexMarketError::InvalidOrderId)?;
+
+    let order_detail = order_book
+        .get_order(&order)
+        .ok_or(DexMarketError::OrderNotFound)?;
+
+    authorize(&order_detail.details.owner)?;
+    rewards::accrue(env)?;
+
+    order_book
+        .cancel_order(&order)
+        .map_err(|_| DexMarketError::InvalidOrderId)?;
+
+    refund_order(env, &market_info, &order_detail)
+}
+
+/// Cancel up to `max_orders` of an owner's orders, returning the number cancelled
+fn remove_owner_orders(env: &Env, owner: &Address, max_orders: u32) -> Result<u32, DexMarketError> {
+    let market_info = market_info(env)?;
+    status::market_status(env).check_cancel_order()?;
+
+    let order_book = order_book_state(env);
+    let mut cancelled = 0;
+
+    rewards::accrue(env)?;
+
+    for _ in 0..max_orders {
+        let Some(order) = owner_orders::first(env, owner) else {
+            break;
+        };
+
+        let Some(entry) = order_book.get_order(&order) else {
+            owner_orders::remove(env, owner, &order);
+            continue;
+        };
+
+        order_book
+            .cancel_order(&order)
+            .map_err(|_| DexMarketError::InvalidOrderId)?;
+
+        refund_order(env, &market_info, &entry)?;
+        cancelled += 1;
+    }
+
+    Ok(cancelled)
+}
+
+/// Validate and place an order, once `authorize` has accepted it
+fn submit_order(
+    env: &Env,
+    params: OrderParams,
+    authorize: impl FnOnce(
+        &DexMarketInfo,
+        &orderbook::OrderParams<OrderDetail>,
+    ) -> Result<(), DexMarketError>,
+) -> Result<(Option<OrderId>, FillSummary), DexMarketError> {
+    use orderbook::OrderSide;
+
+    let referrer = params.referrer.into_address();
+
+    if referrer.as_ref() == Some(&params.owner) {
+        return Err(DexMarketError::InvalidReferrer);
+    }
+
+    let order_book = order_book_state(env);
+    let params = orderbook::OrderParams {
+        side: params.side.into(),
+        size: params.size,
+        price: params.price,
+        details: OrderDetail {
+            owner: params.owner,
+            funding: params.funding,
+        },
+    };
+
+    let market_info = market_info(env)?;
+
+    if params.size < market_info.base_min_order_size {
+        return Err(DexMarketError::InvalidOrderSize);
+    }
+
+    if params.size > MAX_ORDER_SIZE {
+        return Err(DexMarketError::AmountTooLarge);
+    }
+
+    if params.price == 0
+        || params.price > orderbook::MAX_PRICE
+        || params.price % market_info.price_tick != 0
+    {
+        return Err(DexMarketError::InvalidPrice);
+    }
+
+    let market_status = status::market_status(env);
+    market_status.check_place_order()?;
+
+    if market_status == MarketStatus::PostOnly && would_match(&order_book, &params) {
+        return Err(DexMarketError::MarketPostOnly);
+    }
+
+    authorize(&market_info, &params)?;
+
+    // the amount taken to cover the whole order
+    let offer_amount = match params.side {
+        OrderSide::Bid => price::bid_escrow(&market_info, params.price, params.size)?,
+        OrderSide::Ask => params.size as i128,
+    };
+
+    execute_order(
+        env,
+        &market_info,
+        &order_book,
+        &params,
+        referrer.as_ref(),
+        offer_amount,
+        true,
+    )
+}
+
+/// Transfer amounts of tokens held by the market to an address
+fn transfer_all(env: &Env, amounts: Map<Address, i128>, to: &Address) {
+    for (token_address, amount) in amounts {
+        if amount > 0 {
+            token::Client::new(env, &token_address).transfer(
+                &env.current_contract_address(),
+                to,
+                &amount,
+            );
+        }
+    }
+}
+
+/// Forget a cancelled order, and return the tokens held in escrow for it to its owner
+fn refund_order(
+    env: &Env,
+    market_info: &DexMarketInfo,
+    order: &OrderEntry<OrderId, OrderDetail>,
+) -> Result<(), DexMarketError> {
+    use orderbook::OrderSide;
+
+    owner_orders::remove(env, &order.details.owner, &order.id);
+    events::order_cancelled(
+        env,
+        &order.details.owner,
+        OrderCancelledEvent {
+            order_id: order.id.clone(),
+            unfilled_size: order.size,
+        },
+    );
+
+    let (asset, amount) = match order.id.side() {
+        OrderSide::Ask => (Asset::Base, order.size as i128),
+        OrderSide::Bid => (
+            Asset::Quote,
+            price::bid_escrow(market_info, order.price, order.size)?,
+        ),
+    };
+
+    Funds::new(env, market_info).release(&order.details.owner, order.details.funding, asset, amount)
+}
+
+/// Add two token amounts, failing instead of overflowing
+fn add_amounts(amount: i128, other: i128) -> Result<i128, DexMarketError> {
+    amount
+        .checked_add(other)
+        .ok_or(DexMarketError::ArithmeticOverflow)
+}
+
+/// Check that the oracle is asked for the price of the base token
+fn check_oracle_asset(env: &Env, asset: &Address) -> Option<()> {
+    let market_info = market_info(env).ok()?;
+
+    match *asset == market_info.base_token {
+        true => Some(()),
+        false => None,
+    }
+}
+
+/// Check that an order which only takes liquidity can be placed by a trader
+fn check_fill_order(env: &Env, trader: &Address) -> Result<(), DexMarketError> {
+    let market_status = status::market_status(env);
+    market_status.check_place_order()?;
+
+    if market_status == MarketStatus::PostOnly {
+        return Err(DexMarketError::MarketPostOnly);
+    }
+
+    trader.require_auth();
+
+    Ok(())
+}
+
+/// Fill the part of the book covered by a plan, without posting anything to the book
+fn execute_fill(
+    env: &Env,
+    market_info: &DexMarketInfo,
+    order_book: &OrderBook<OrderDetail>,
+    trader: Address,
+    side: OrderSide,
+    plan: &swap::FillPlan,
+) -> Result<FillSummary, DexMarketError> {
+    if plan.size == 0 {
+        return Ok(FillSummary {
+            posted_size: 0,
+            base_filled: 0,
+            quote_filled: 0,
+            taker_fee: 0,
+            base_fees: 0,
+            quote_fees: 0,
+        });
+    }
+
+    // the worst possible price, since the fills are only limited by the amounts
+    let price = match side {
+        OrderSide::Bid => u64::MAX,
+        OrderSide::Ask => 0,
+    };
+
+    let params = orderbook::OrderParams {
+        side: side.into(),
+        size: plan.size,
+        price,
+        details: OrderDetail {
+            owner: trader,
+            funding: Funding::Wallet,
+        },
+    };
+
+    let (_, summary) = execute_order(
+        env,
+        market_info,
+        order_book,
+        &params,
+        None,
+        plan.amount_in,
+        false,
+    )?;
+
+    Ok(summary)
+}
+
+/// The prefix of the order book
+///
+/// Version 0 of the storage layout kept its book, with different order details, under 0xF1A0.
+const ORDER_BOOK_PREFIX: u16 = 0xF1A1;
+
+fn order_book_state(env: &Env) -> OrderBook<OrderDetail> {
+    OrderBook::open(env, ORDER_BOOK_PREFIX)
+}
+
+/// The price of the best order on a side of the book
+fn best_price(order_book: &OrderBook<OrderDetail>, side: orderbook::OrderSide) -> Option<u64> {
+    order_book
+        .orders(side)
+        .into_iter()
+        .next()
+        .map(|order| order.price())
+}
+
+/// The price halfway between the best bid and the best ask, rounded down
+fn mid_price(order_book: &OrderBook<OrderDetail>) -> Option<u64> {
+    use orderbook::OrderSide;
+
+    match (
+        best_price(order_book, OrderSide::Bid),
+        best_price(order_book, OrderSide::Ask),
+    ) {
+        (Some(bid), Some(ask)) => Some(bid + (ask - bid) / 2),
+        _ => None,
+    }
+}
+
+/// Check if an order would match against the best order on the other side of the book
+fn would_match(
+    order_book: &OrderBook<OrderDetail>,
+    params: &orderbook::OrderParams<OrderDetail>,
+) -> bool {
+    use orderbook::OrderSide;
+
+    let Some(best) = order_book.orders(params.side.opposite()).into_iter().next() else {
+        return false;
+    };
+
+    match params.side {
+        OrderSide::Bid => best.price() <= params.price,
+        OrderSide::Ask => best.price() >= params.price,
+    }
+}
+
+#[contracttype]
+struct OrderDetail {
+    owner: Address,
+    funding: Funding,
+}
+
+const MARKET_INFO: Symbol = symbol_short!("MARKETINF");
+
+fn market_info(env: &Env) -> Result<DexMarketInfo, DexMarketError> {
+    if !env.storage().instance().has(&MARKET_INFO) {
+        return Err(DexMarketError::NotInitialized);
+    }
+
+    migration::check_schema_version(env)?;
+
+    env.storage()
+        .instance()
+        .get(&MARKET_INFO)
+        .ok_or(DexMarketError::NotInitialized)
+}
+
+fn set_market_info(env: &Env, info: &DexMarketInfo) {
+    env.storage().instance().set(&MARKET_INFO, info);
+    events::market_info_changed(env, info);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestEnv {
+        env: Env,
+        base_token: Address,
+        quote_token: Address,
+        admin: Address,
+        fee_authority: Address,
+        users: std::vec::Vec<Address>,
+        market: Address,
+    }
+
+    impl TestEnv {
+        fn new() -> Self {
+            Self::with_fees(0, 0)
+        }
+
+        fn with_fees(maker_fee_bps: i32, taker_fee_bps: u32) -> Self {
+            use soroban_sdk::testutils::Address;
+
+            let env = Env::default();
+            let market = env.register_contract(None, DexMarketContract);
+            let admin = soroban_sdk::Address::random(&env);
+            let fee_authority = soroban_sdk::Address::random(&env);
+
+            env.mock_all_auths();
+
+            let new_token = |symbol| {
+                let token = env.register_contract(None, test_token::Token);
+                let symbol = soroban_sdk::String::from_slice(&env, symbol);
+                test_token::TokenClient::new(&env, &token).initialize(&admin, &0, &symbol, &symbol);
+
+                token
+            };
+
+            let base_token = new_token("BASE");
+            let quote_token = new_token("QUOTE");
+
+            let market_client = DexMarketContractClient::new(&env, &market);
+            market_client.init(
+                &admin,
+                &DexMarketInfo {
+                    base_token: base_token.clone(),
+                    quote_token: quote_token.clone(),
+                    base_min_order_size: 1,
+                    price_tick: 1,
+                    fee_authority: fee_authority.clone(),
+                    maker_fee_bps,
+                    taker_fee_bps,
+                    base_decimals: 0,
+                    quote_decimals: 0,
+                },
+            );
+
+            let users = vec![
+                soroban_sdk::Address::random(&env),
+                soroban_sdk::Address::random(&env),
+            ];
+
+            Self {
+                env,
+                base_token,
+                quote_token,
+                admin,
+                fee_authority,
+                market,
+                users,
+            }
+        }
+
+        fn market_client(&self) -> DexMarketContractClient {
+            DexMarketContractClient::new(&self.env, &self.market)
+        }
+
+        fn base_client(&self) -> test_token::TokenClient {
+            test_token::TokenClient::new(&self.env, &self.base_token)
+        }
+
+        fn quote_client(&self) -> test_token::TokenClient {
+            test_token::TokenClient::new(&self.env, &self.quote_token)
+        }
+    }
+
+    #[test]
+    fn test_simple_swap() {
+        let ctx = TestEnv::new();
+
+        let market = ctx.market_client();
+
+        ctx.env.mock_all_auths();
+
+        ctx.base_client().mint(&ctx.users[0], &125);
+        ctx.quote_client().mint(&ctx.users[1], &100);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 125,
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 100,
+            price: (1 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        let balance_0_quote = ctx.quote_client().balance(&ctx.users[0]);
+        let balance_1_base = ctx.base_client().balance(&ctx.users[1]);
+
+        assert_eq!(100, balance_0_quote);
+        assert_eq!(100, balance_1_base);
+    }
+
+    #[test]
+    fn test_price_limit_matching() {
+        let ctx = TestEnv::new();
+
+        let market = ctx.market_client();
+
+        ctx.env.mock_all_auths();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &3_000);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 1_000,
+            price: (2 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 1_000,
+            price: (3 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        let balance_0_quote = ctx.quote_client().balance(&ctx.users[0]);
+        let balance_1_base = ctx.base_client().balance(&ctx.users[1]);
+
+        let balance_0_base = ctx.base_client().balance(&ctx.users[0]);
+        let balance_1_quote = ctx.quote_client().balance(&ctx.users[1]);
+
+        assert_eq!(0, balance_0_base);
+        assert_eq!(2_000, balance_0_quote);
 
         assert_eq!(1_000, balance_1_base);
         assert_eq!(1_000, balance_1_quote);
     }
 
     #[test]
-    fn test_multiple_matching() {
+    fn test_multiple_matching() {
+        let ctx = TestEnv::new();
+
+        let market = ctx.market_client();
+
+        ctx.env.mock_all_auths();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &3_000);
+
+        for i in 1..5 {
+            market.place_order(&OrderParams {
+                side: OrderSide::Ask,
+                price: (i << 32),
+                size: 100 * i as u128,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        }
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 1_000,
+            price: (3 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        let balance_0_quote = ctx.quote_client().balance(&ctx.users[0]);
+        let balance_1_base = ctx.base_client().balance(&ctx.users[1]);
+
+        let balance_0_base = ctx.base_client().balance(&ctx.users[0]);
+        let balance_1_quote = ctx.quote_client().balance(&ctx.users[1]);
+
+        assert_eq!(0, balance_0_base);
+        assert_eq!(600, balance_1_base);
+
+        assert_eq!(1_400, balance_0_quote);
+        assert_eq!(4_00, balance_1_quote);
+    }
+
+    #[test]
+    fn test_taker_and_maker_fees() {
+        let ctx = TestEnv::with_fees(10, 30);
+
+        let market = ctx.market_client();
+
+        ctx.env.mock_all_auths();
+
+        ctx.base_client().mint(&ctx.users[0], &10_000);
+        ctx.quote_client().mint(&ctx.users[1], &10_000);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 10_000,
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        let (posted_id, result) = market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 10_000,
+            price: (1 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(None, posted_id);
+        assert_eq!(10_000, result.base_filled);
+        assert_eq!(10_000, result.quote_filled);
+        assert_eq!(30, result.taker_fee);
+        assert_eq!(30, result.base_fees);
+        assert_eq!(10, result.quote_fees);
+
+        assert_eq!(9_990, ctx.quote_client().balance(&ctx.users[0]));
+        assert_eq!(9_970, ctx.base_client().balance(&ctx.users[1]));
+
+        market.withdraw_fees(&ctx.fee_authority);
+
+        assert_eq!(30, ctx.base_client().balance(&ctx.fee_authority));
+        assert_eq!(10, ctx.quote_client().balance(&ctx.fee_authority));
+        assert_eq!(0, ctx.base_client().balance(&ctx.market));
+        assert_eq!(0, ctx.quote_client().balance(&ctx.market));
+    }
+
+    #[test]
+    fn test_maker_rebate() {
+        let ctx = TestEnv::with_fees(-10, 30);
+
+        let market = ctx.market_client();
+
+        ctx.env.mock_all_auths();
+
+        ctx.quote_client().mint(&ctx.users[0], &10_000);
+        ctx.base_client().mint(&ctx.users[1], &10_000);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 10_000,
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        let (_, result) = market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 10_000,
+            price: (1 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(30, result.taker_fee);
+        assert_eq!(0, result.base_fees);
+        assert_eq!(20, result.quote_fees);
+
+        assert_eq!(10_000, ctx.base_client().balance(&ctx.users[0]));
+        assert_eq!(10, ctx.quote_client().balance(&ctx.users[0]));
+        assert_eq!(9_970, ctx.quote_client().balance(&ctx.users[1]));
+
+        market.withdraw_fees(&ctx.fee_authority);
+
+        assert_eq!(20, ctx.quote_client().balance(&ctx.fee_authority));
+        assert_eq!(0, ctx.quote_client().balance(&ctx.market));
+    }
+
+    #[test]
+    fn test_rebate_larger_than_taker_fee_is_rejected() {
+        use soroban_sdk::testutils::Address as _;
+
+        let env = Env::default();
+        let market = env.register_contract(None, DexMarketContract);
+
+        env.mock_all_auths();
+
+        let admin = Address::random(&env);
+        let result = DexMarketContractClient::new(&env, &market).try_init(
+            &admin,
+            &DexMarketInfo {
+                base_token: Address::random(&env),
+                quote_token: Address::random(&env),
+                base_min_order_size: 1,
+                price_tick: 1,
+                fee_authority: Address::random(&env),
+                maker_fee_bps: -40,
+                taker_fee_bps: 30,
+                base_decimals: 0,
+                quote_decimals: 0,
+            },
+        );
+
+        assert_eq!(Err(Ok(DexMarketError::InvalidFeeSchedule)), result);
+    }
+
+    #[test]
+    fn test_init_only_once() {
+        let ctx = TestEnv::new();
+
+        let market = ctx.market_client();
+        let result = market.try_init(
+            &ctx.users[0],
+            &DexMarketInfo {
+                base_token: ctx.base_token.clone(),
+                quote_token: ctx.quote_token.clone(),
+                base_min_order_size: 1,
+                price_tick: 1,
+                fee_authority: ctx.users[0].clone(),
+                maker_fee_bps: 0,
+                taker_fee_bps: 0,
+                base_decimals: 0,
+                quote_decimals: 0,
+            },
+        );
+
+        assert_eq!(Err(Ok(DexMarketError::AlreadyInitialized)), result);
+    }
+
+    #[test]
+    fn test_requires_init() {
+        let env = Env::default();
+        let market = env.register_contract(None, DexMarketContract);
+        let market = DexMarketContractClient::new(&env, &market);
+
+        env.mock_all_auths();
+
+        let result = market.try_set_min_order_size(&10);
+        assert_eq!(Err(Ok(DexMarketError::NotInitialized)), result);
+    }
+
+    #[test]
+    fn test_admin_transfer() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        let new_admin = ctx.users[0].clone();
+
+        assert_eq!(
+            Err(Ok(DexMarketError::NoPendingAdmin)),
+            market.try_accept_admin()
+        );
+
+        market.set_admin(&new_admin);
+        assert_eq!(ctx.admin, ctx.env.auths()[0].0);
+
+        market.accept_admin();
+        assert_eq!(new_admin, ctx.env.auths()[0].0);
+
+        market.set_fee_schedule(&5, &10);
+        assert_eq!(new_admin, ctx.env.auths()[0].0);
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidFeeSchedule)),
+            market.try_set_fee_schedule(&-20, &10)
+        );
+    }
+
+    #[test]
+    fn test_market_status() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        let ask = |price: u64| OrderParams {
+            side: OrderSide::Ask,
+            size: 100,
+            price: price << 32,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        let bid = |price: u64| OrderParams {
+            side: OrderSide::Bid,
+            size: 100,
+            price: price << 32,
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        assert_eq!(MarketStatus::Active, market.status());
+
+        market.set_status(&MarketStatus::PostOnly);
+        assert_eq!(ctx.admin, ctx.env.auths()[0].0);
+        assert_eq!(MarketStatus::PostOnly, market.status());
+
+        let (posted_ask, _) = market.place_order(&ask(2));
+        let posted_ask = posted_ask.unwrap();
+
+        assert!(market.try_place_order(&bid(1)).is_ok());
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketPostOnly)),
+            market.try_place_order(&bid(2))
+        );
+
+        market.set_status(&MarketStatus::CancelOnly);
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketCancelOnly)),
+            market.try_place_order(&ask(3))
+        );
+
+        market.set_status(&MarketStatus::Halted);
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketHalted)),
+            market.try_place_order(&ask(3))
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketHalted)),
+            market.try_cancel_order(&posted_ask)
+        );
+
+        market.set_status(&MarketStatus::CancelOnly);
+        market.cancel_order(&posted_ask);
+
+        assert_eq!(1_000, ctx.base_client().balance(&ctx.users[0]));
+    }
+
+    #[test]
+    fn test_close_market() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        for price in 3..6 {
+            market.place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 100,
+                price: price << 32,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        }
+
+        for price in 1..3 {
+            market.place_order(&OrderParams {
+                side: OrderSide::Bid,
+                size: 100,
+                price: price << 32,
+                owner: ctx.users[1].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        }
+
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketNotClosing)),
+            market.try_settle_closing(&10)
+        );
+
+        market.close_market();
+        assert_eq!(MarketStatus::Closing, market.status());
+
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketClosing)),
+            market.try_place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 100,
+                price: 1 << 32,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            })
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::MarketClosing)),
+            market.try_set_status(&MarketStatus::Active)
+        );
+
+        assert!(!market.settle_closing(&2));
+        assert!(!market.settle_closing(&2));
+        assert!(market.settle_closing(&2));
+
+        assert_eq!(MarketStatus::Closed, market.status());
+        assert_eq!(1_000, ctx.base_client().balance(&ctx.users[0]));
+        assert_eq!(1_000, ctx.quote_client().balance(&ctx.users[1]));
+        assert_eq!(0, ctx.base_client().balance(&ctx.market));
+        assert_eq!(0, ctx.quote_client().balance(&ctx.market));
+    }
+
+    #[test]
+    fn test_vault_funded_orders() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &2_000);
+
+        market.deposit(&ctx.users[0], &1_000, &0);
+        market.deposit(&ctx.users[1], &0, &2_000);
+
+        assert_eq!(0, ctx.base_client().balance(&ctx.users[0]));
+        assert_eq!(1_000, ctx.base_client().balance(&ctx.market));
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 1_000,
+            price: (2 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Vault,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(
+            VaultBalance {
+                base_free: 0,
+                base_locked: 1_000,
+                quote_free: 0,
+                quote_locked: 0,
+            },
+            market.balance(&ctx.users[0])
+        );
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 400,
+            price: (2 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Vault,
+            referrer: Referrer::None,
+        });
+
+        // fills are credited internally, without moving any tokens
+        assert_eq!(1_000, ctx.base_client().balance(&ctx.market));
+        assert_eq!(2_000, ctx.quote_client().balance(&ctx.market));
+
+        assert_eq!(
+            VaultBalance {
+                base_free: 0,
+                base_locked: 600,
+                quote_free: 800,
+                quote_locked: 0,
+            },
+            market.balance(&ctx.users[0])
+        );
+        assert_eq!(
+            VaultBalance {
+                base_free: 400,
+                base_locked: 0,
+                quote_free: 1_200,
+                quote_locked: 0,
+            },
+            market.balance(&ctx.users[1])
+        );
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientBalance)),
+            market.try_withdraw(&ctx.users[0], &1, &0)
+        );
+
+        market.withdraw(&ctx.users[0], &0, &800);
+        market.withdraw(&ctx.users[1], &400, &1_200);
+
+        assert_eq!(800, ctx.quote_client().balance(&ctx.users[0]));
+        assert_eq!(400, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(1_200, ctx.quote_client().balance(&ctx.users[1]));
+        assert_eq!(VaultBalance::default(), market.balance(&ctx.users[1]));
+    }
+
+    #[test]
+    fn test_vault_and_wallet_orders_match() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &500);
+        ctx.quote_client().mint(&ctx.users[1], &500);
+
+        market.deposit(&ctx.users[0], &500, &0);
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientBalance)),
+            market.try_place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 501,
+                price: (1 << 32),
+                owner: ctx.users[0].clone(),
+                funding: Funding::Vault,
+                referrer: Referrer::None,
+            })
+        );
+
+        let (posted_id, _) = market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 500,
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Vault,
+            referrer: Referrer::None,
+        });
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 200,
+            price: (1 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(200, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(300, ctx.quote_client().balance(&ctx.users[1]));
+
+        market.cancel_order(&posted_id.unwrap());
+
+        assert_eq!(
+            VaultBalance {
+                base_free: 300,
+                base_locked: 0,
+                quote_free: 200,
+                quote_locked: 0,
+            },
+            market.balance(&ctx.users[0])
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidAmount)),
+            market.try_deposit(&ctx.users[0], &-1, &0)
+        );
+    }
+
+    #[test]
+    fn test_undeliverable_maker_payout() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 1_000,
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        ctx.quote_client().set_frozen(&ctx.users[0], &true);
+        ctx.base_client().set_frozen(&ctx.users[0], &true);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 400,
+            price: (1 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(400, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(
+            UnsettledFunds {
+                base: 0,
+                quote: 400
+            },
+            market.unsettled(&ctx.users[0])
+        );
+
+        // the crank isn't blocked by the frozen account either
+        market.close_market();
+        assert!(market.settle_closing(&10));
+
+        assert_eq!(
+            UnsettledFunds {
+                base: 600,
+                quote: 400
+            },
+            market.unsettled(&ctx.users[0])
+        );
+        assert!(market.try_claim(&ctx.users[0]).is_err());
+
+        ctx.quote_client().set_frozen(&ctx.users[0], &false);
+        ctx.base_client().set_frozen(&ctx.users[0], &false);
+
+        market.claim(&ctx.users[0]);
+
+        assert_eq!(600, ctx.base_client().balance(&ctx.users[0]));
+        assert_eq!(400, ctx.quote_client().balance(&ctx.users[0]));
+        assert_eq!(UnsettledFunds::default(), market.unsettled(&ctx.users[0]));
+    }
+
+    #[test]
+    fn test_order_events() {
+        use soroban_sdk::{testutils::Events, IntoVal, TryFromVal, Val};
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        let (maker_id, _) = market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 300,
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+        let maker_id = maker_id.unwrap();
+
+        let (taker_id, _) = market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 500,
+            price: (1 << 32),
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+        let taker_id = taker_id.unwrap();
+
+        market.cancel_order(&taker_id);
+
+        let market_events = ctx
+            .env
+            .events()
+            .all()
+            .iter()
+            .filter(|(contract, _, _)| *contract == ctx.market)
+            .map(|(_, topics, data)| (topics, data))
+            .collect::<std::vec::Vec<_>>();
+
+        let topics = |items: &[Val]| soroban_sdk::Vec::from_slice(&ctx.env, items);
+        let placed = symbol_short!("placed").into_val(&ctx.env);
+        let trade = symbol_short!("trade").into_val(&ctx.env);
+        let filled = symbol_short!("filled").into_val(&ctx.env);
+        let cancelled = symbol_short!("cancelled").into_val(&ctx.env);
+        let maker = ctx.users[0].into_val(&ctx.env);
+        let taker = ctx.users[1].into_val(&ctx.env);
+
+        // config events from init come first
+        let order_events = &market_events[market_events.len() - 5..];
+
+        assert_eq!(topics(&[placed, maker]), order_events[0].0);
+        assert_eq!(topics(&[trade, maker, taker]), order_events[1].0);
+        assert_eq!(topics(&[filled, maker]), order_events[2].0);
+        assert_eq!(topics(&[placed, taker]), order_events[3].0);
+        assert_eq!(topics(&[cancelled, taker]), order_events[4].0);
+
+        let trade_event = TradeEvent::try_from_val(&ctx.env, &order_events[1].1).unwrap();
+        assert_eq!(
+            TradeEvent {
+                maker_order_id: maker_id,
+                maker_side: OrderSide::Ask,
+                price: 1 << 32,
+                size: 300,
+                quote_amount: 300,
+                taker_fee: 0,
+                maker_fee: 0,
+                maker_rebate: 0,
+            },
+            trade_event
+        );
+
+        let cancelled_event =
+            OrderCancelledEvent::try_from_val(&ctx.env, &order_events[4].1).unwrap();
+        assert_eq!(200, cancelled_event.unfilled_size);
+    }
+
+    #[test]
+    fn test_price_history() {
+        use soroban_sdk::{testutils::Ledger, vec};
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &10_000);
+        ctx.quote_client().mint(&ctx.users[1], &10_000);
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidCandleInterval)),
+            market.try_set_candle_intervals(&vec![&ctx.env, 60, 0])
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidCandleInterval)),
+            market.try_set_candle_intervals(&vec![&ctx.env, 60, 60])
+        );
+
+        market.set_candle_intervals(&vec![&ctx.env, 60, 3_600]);
+        assert_eq!(None, market.last_trade());
+
+        for price in [2, 3, 1] {
+            market.place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 100,
+                price: price << 32,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        }
+
+        let buy = |size: u128| OrderParams {
+            side: OrderSide::Bid,
+            size,
+            price: 3 << 32,
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        ctx.env.ledger().with_mut(|ledger| ledger.timestamp = 7_210);
+        market.place_order(&buy(150));
+
+        ctx.env.ledger().with_mut(|ledger| ledger.timestamp = 7_270);
+        market.place_order(&buy(150));
+
+        assert_eq!(
+            Some(LastTrade {
+                price: 3 << 32,
+                timestamp: 7_270
+            }),
+            market.last_trade()
+        );
+
+        assert_eq!(
+            vec![
+                &ctx.env,
+                Candle {
+                    start: 7_200,
+                    open: 1 << 32,
+                    high: 3 << 32,
+                    low: 1 << 32,
+                    close: 3 << 32,
+                    base_volume: 300,
+                    quote_volume: 600,
+                }
+            ],
+            market.candles(&3_600, &10)
+        );
+
+        let minute_candles = market.candles(&60, &10);
+        assert_eq!(2, minute_candles.len());
+        assert_eq!(7_260, minute_candles.get(1).unwrap().start);
+        assert_eq!(2 << 32, minute_candles.get(1).unwrap().open);
+        assert_eq!(minute_candles.slice(1..), market.candles(&60, &1));
+
+        assert_eq!(
+            Volume {
+                base: 300,
+                quote: 600
+            },
+            market.volume_24h()
+        );
+
+        ctx.env
+            .ledger()
+            .with_mut(|ledger| ledger.timestamp = 7_270 + 86_400);
+        assert_eq!(Volume::default(), market.volume_24h());
+
+        market.set_candle_intervals(&vec![&ctx.env, 3_600]);
+        assert_eq!(0, market.candles(&60, &10).len());
+    }
+
+    #[test]
+    fn test_oracle_prices() {
+        use soroban_sdk::testutils::Ledger;
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &10_000);
+        ctx.quote_client().mint(&ctx.users[1], &10_000);
+
+        market.set_oracle_resolution(&100);
+        assert_eq!(14, market.decimals());
+        assert_eq!(ctx.quote_token, market.base());
+
+        let trade_at = |timestamp: u64, price: u64| {
+            ctx.env
+                .ledger()
+                .with_mut(|ledger| ledger.timestamp = timestamp);
+
+            market.place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 10,
+                price: price << 32,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+            market.place_order(&OrderParams {
+                side: OrderSide::Bid,
+                size: 10,
+                price: price << 32,
+                owner: ctx.users[1].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        };
+
+        trade_at(1_000, 2);
+        trade_at(1_150, 4);
+        trade_at(1_425, 3);
+
+        ctx.env.ledger().with_mut(|ledger| ledger.timestamp = 1_500);
+
+        let decimal = |price: i128| price * 10i128.pow(14) / 100;
+        let price_data = |price: i128, timestamp: u64| PriceData {
+            price: decimal(price),
+            timestamp,
+        };
+
+        assert_eq!(
+            Some(price_data(325, 1_500)),
+            market.lastprice(&ctx.base_token)
+        );
+        assert_eq!(None, market.lastprice(&ctx.quote_token));
+
+        let prices = market.prices(&ctx.base_token, &10).unwrap();
+        assert_eq!(
+            soroban_sdk::vec![
+                &ctx.env,
+                price_data(325, 1_500),
+                price_data(400, 1_400),
+                price_data(400, 1_300),
+                price_data(300, 1_200),
+                price_data(200, 1_100),
+            ],
+            prices
+        );
+
+        assert_eq!(
+            Some(price_data(400, 1_300)),
+            market.price(&ctx.base_token, &1_350)
+        );
+        assert_eq!(
+            Some(price_data(325, 1_500)),
+            market.twap(&ctx.base_token, &5)
+        );
+        assert_eq!(None, market.twap(&ctx.base_token, &6));
+
+        // the history is discarded when the resolution changes
+        market.set_oracle_resolution(&50);
+        assert_eq!(
+            Some(price_data(300, 1_500)),
+            market.lastprice(&ctx.base_token)
+        );
+        assert_eq!(None, market.twap(&ctx.base_token, &2));
+    }
+
+    #[test]
+    fn test_price_tick() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+
+        let ask = |price: u64| OrderParams {
+            side: OrderSide::Ask,
+            size: 100,
+            price,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidPrice)),
+            market.try_place_order(&ask(0))
+        );
+
+        let other_market = DexMarketContractClient::new(
+            &ctx.env,
+            &ctx.env.register_contract(None, DexMarketContract),
+        );
+        let info = |price_tick: u64| DexMarketInfo {
+            base_token: ctx.base_token.clone(),
+            quote_token: ctx.quote_token.clone(),
+            base_min_order_size: 1,
+            price_tick,
+            fee_authority: ctx.fee_authority.clone(),
+            maker_fee_bps: 0,
+            taker_fee_bps: 0,
+            base_decimals: 0,
+            quote_decimals: 0,
+        };
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidTickSize)),
+            other_market.try_init(&ctx.admin, &info(0))
+        );
+
+        other_market.init(&ctx.admin, &info(1 << 16));
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidPrice)),
+            other_market.try_place_order(&ask((1 << 32) + 1))
+        );
+    }
+
+    #[test]
+    fn test_quote_fill() {
+        let ctx = TestEnv::with_fees(0, 100);
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &200);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        for price in [1 << 32, 2 << 32] {
+            market.place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 100,
+                price,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        }
+
+        // spending a fixed amount of quote tokens walks up the book
+        assert_eq!(
+            FillQuote {
+                amount_in: 150,
+                amount_out: 123,
+            },
+            market.quote_fill(&OrderSide::Bid, &150, &i128::MAX)
+        );
+        assert_eq!(
+            (150, 123),
+            market.swap_exact_in(&ctx.users[1], &OrderSide::Bid, &150, &0)
+        );
+
+        // receiving a fixed amount after fees only takes what is needed, with the fee rounded up
+        assert_eq!(
+            FillQuote {
+                amount_in: 102,
+                amount_out: 50,
+            },
+            market.quote_fill(&OrderSide::Bid, &1_000, &50)
+        );
+        assert_eq!(
+            102,
+            market.swap_exact_out(&ctx.users[1], &OrderSide::Bid, &50, &1_000)
+        );
+        assert_eq!(173, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(748, ctx.quote_client().balance(&ctx.users[1]));
+
+        // nothing is left resting, and the unused budget never leaves the wallet
+        assert_eq!(
+            FillQuote {
+                amount_in: 48,
+                amount_out: 23,
+            },
+            market.quote_fill(&OrderSide::Bid, &1_000, &i128::MAX)
+        );
+        assert_eq!(
+            (48, 23),
+            market.swap_exact_in(&ctx.users[1], &OrderSide::Bid, &1_000, &0)
+        );
+        assert_eq!(700, ctx.quote_client().balance(&ctx.users[1]));
+        assert_eq!(0, ctx.quote_client().balance(&ctx.market));
+        assert_eq!(
+            FillQuote {
+                amount_in: 0,
+                amount_out: 0,
+            },
+            market.quote_fill(&OrderSide::Bid, &1_000, &i128::MAX)
+        );
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidAmount)),
+            market.try_quote_fill(&OrderSide::Bid, &-1, &i128::MAX)
+        );
+    }
+
+    #[test]
+    fn test_swap_exact_in_and_out() {
+        let ctx = TestEnv::with_fees(0, 100);
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &200);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        for price in [1 << 32, 2 << 32] {
+            market.place_order(&OrderParams {
+                side: OrderSide::Ask,
+                size: 100,
+                price,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            });
+        }
+
+        // 150 quote tokens buy 100 base at 1 and 25 at 2, less a fee rounded up for each fill
+        assert_eq!(
+            Err(Ok(DexMarketError::SlippageExceeded)),
+            market.try_swap_exact_in(&ctx.users[1], &OrderSide::Bid, &150, &124)
+        );
+        assert_eq!(
+            (150, 123),
+            market.swap_exact_in(&ctx.users[1], &OrderSide::Bid, &150, &123)
+        );
+        assert_eq!(123, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(850, ctx.quote_client().balance(&ctx.users[1]));
+
+        // receiving 50 after fees means buying 51 at 2
+        assert_eq!(
+            Err(Ok(DexMarketError::SlippageExceeded)),
+            market.try_swap_exact_out(&ctx.users[1], &OrderSide::Bid, &50, &101)
+        );
+        assert_eq!(
+            102,
+            market.swap_exact_out(&ctx.users[1], &OrderSide::Bid, &50, &102)
+        );
+        assert_eq!(173, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(748, ctx.quote_client().balance(&ctx.users[1]));
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientLiquidity)),
+            market.try_swap_exact_out(&ctx.users[1], &OrderSide::Bid, &100, &1_000)
+        );
+
+        // selling base tokens into the bids
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 100,
+            price: 1 << 32,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(
+            (50, 49),
+            market.swap_exact_in(&ctx.users[1], &OrderSide::Ask, &50, &49)
+        );
+        assert_eq!(123, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(797, ctx.quote_client().balance(&ctx.users[1]));
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidAmount)),
+            market.try_swap_exact_in(&ctx.users[1], &OrderSide::Ask, &0, &0)
+        );
+
+        // the rest of the bids only take 50 of the input, and the rest stays with the trader
+        assert_eq!(
+            Err(Ok(DexMarketError::SlippageExceeded)),
+            market.try_swap_exact_in(&ctx.users[1], &OrderSide::Ask, &51, &50)
+        );
+        assert_eq!(
+            (50, 49),
+            market.swap_exact_in(&ctx.users[1], &OrderSide::Ask, &51, &49)
+        );
+        assert_eq!(73, ctx.base_client().balance(&ctx.users[1]));
+        assert_eq!(846, ctx.quote_client().balance(&ctx.users[1]));
+
+        // nothing is left to fill against
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientLiquidity)),
+            market.try_swap_exact_in(&ctx.users[1], &OrderSide::Ask, &50, &0)
+        );
+        assert_eq!(73, ctx.base_client().balance(&ctx.users[1]));
+    }
+
+    #[test]
+    fn test_token_decimals() {
+        let ctx = TestEnv::new();
+
+        // a stellar asset has 7 decimals, and the test tokens have none
+        let asset = ctx.env.register_stellar_asset_contract(ctx.admin.clone());
+        let asset_client = token::Client::new(&ctx.env, &asset);
+        let asset_admin = token::StellarAssetClient::new(&ctx.env, &asset);
+
+        let new_market = |base_token: &Address, quote_token: &Address| {
+            let market = DexMarketContractClient::new(
+                &ctx.env,
+                &ctx.env.register_contract(None, DexMarketContract),
+            );
+
+            market.init(
+                &ctx.admin,
+                &DexMarketInfo {
+                    base_token: base_token.clone(),
+                    quote_token: quote_token.clone(),
+                    base_min_order_size: 1,
+                    price_tick: 1,
+                    fee_authority: ctx.fee_authority.clone(),
+                    maker_fee_bps: 0,
+                    taker_fee_bps: 0,
+                    base_decimals: 0,
+                    quote_decimals: 0,
+                },
+            );
+
+            market
+        };
+
+        // 1.5 whole base tokens at 3 quote tokens each, which the taker pays rounded up and the maker
+        // receives rounded down
+        let market = new_market(&asset, &ctx.quote_token);
+        asset_admin.mint(&ctx.users[0], &20_000_000);
+        ctx.quote_client().mint(&ctx.users[1], &10);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 20_000_000,
+            price: 3 << 32,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+        let (_, result) = market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 15_000_000,
+            price: 3 << 32,
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(5, result.quote_filled);
+        assert_eq!(15_000_000, asset_client.balance(&ctx.users[1]));
+        assert_eq!(5, ctx.quote_client().balance(&ctx.users[1]));
+        assert_eq!(4, ctx.quote_client().balance(&ctx.users[0]));
+        assert_eq!(Some(1), market.dust().get(ctx.quote_token.clone()));
+
+        // 3 whole base tokens at 1.5 quote tokens each
+        let market = new_market(&ctx.base_token, &asset);
+        ctx.base_client().mint(&ctx.users[0], &3);
+        asset_admin.mint(&ctx.users[1], &45_000_000);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 3,
+            price: 3 << 31,
+            owner: ctx.users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        // the whole quote amount is held in escrow
+        assert_eq!(15_000_000, asset_client.balance(&ctx.users[1]));
+
+        let (_, result) = market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 3,
+            price: 3 << 31,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        assert_eq!(45_000_000, result.quote_filled);
+        assert_eq!(45_000_000, asset_client.balance(&ctx.users[0]));
+        assert_eq!(3, ctx.base_client().balance(&ctx.users[1]));
+    }
+
+    #[test]
+    fn test_solvency() {
+        use soroban_sdk::testutils::Address as _;
+
+        for (maker_fee_bps, taker_fee_bps) in [(10, 30), (-10, 30), (0, 0)] {
+            let ctx = TestEnv::with_fees(maker_fee_bps, taker_fee_bps);
+            let market = ctx.market_client();
+            let market_info = market.market_info();
+            let users: std::vec::Vec<_> = (0..4).map(|_| Address::random(&ctx.env)).collect();
+
+            // the budget covers the whole sequence rather than a single transaction
+            ctx.env.budget().reset_unlimited();
+
+            for user in &users {
+                ctx.base_client().mint(user, &1_000_000);
+                ctx.quote_client().mint(user, &1_000_000);
+                market.deposit(user, &100_000, &100_000);
+                ctx.base_client()
+                    .approve(user, &ctx.market, &200_000, &1_000);
+                ctx.quote_client()
+                    .approve(user, &ctx.market, &200_000, &1_000);
+            }
+
+            // the market holds exactly what it owes: vault balances, unsettled payouts, the
+            // escrow of orders funded from outside the vault, fees and dust
+            let assert_solvent = |orders: &[OrderId]| {
+                let owed = ctx.env.as_contract(&ctx.market, || {
+                    let env = &ctx.env;
+                    let order_book = order_book_state(env);
+                    let mut owed = [0i128; 2];
+
+                    for user in &users {
+                        let balance = funds::vault_balance(env, user);
+                        let unsettled = funds::unsettled_funds(env, user);
+
+                        owed[0] += balance.base_free + balance.base_locked + unsettled.base;
+                        owed[1] += balance.quote_free + balance.quote_locked + unsettled.quote;
+                    }
+
+                    for order in orders {
+                        let Some(entry) = order_book.get_order(order) else {
+                            continue;
+                        };
+
+                        match (entry.details.funding, order.side()) {
+                            (Funding::Vault, _) => {}
+                            (_, orderbook::OrderSide::Ask) => owed[0] += entry.size as i128,
+                            (_, orderbook::OrderSide::Bid) => {
+                                owed[1] += price::bid_escrow(&market_info, entry.price, entry.size)
+                                    .unwrap()
+                            }
+                        }
+                    }
+
+                    for (index, token) in [&market_info.base_token, &market_info.quote_token]
+                        .into_iter()
+                        .enumerate()
+                    {
+                        owed[index] += fees::accrued(env).get(token.clone()).unwrap_or(0)
+                            + fees::dust(env).get(token.clone()).unwrap_or(0);
+                    }
+
+                    owed
+                });
+
+                assert_eq!(owed[0], ctx.base_client().balance(&ctx.market));
+                assert_eq!(owed[1], ctx.quote_client().balance(&ctx.market));
+            };
+
+            // a simple generator, so the sequence of orders is the same on every run
+            let mut seed = 0x2545_F491_4F6C_DD1Du64;
+            let mut random = |limit: u64| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed % limit
+            };
+
+            let mut orders = std::vec::Vec::new();
+            let (mut placed, mut filled, mut cancelled) = (0, 0, 0);
+
+            for _ in 0..150 {
+                let owner = users[random(4) as usize].clone();
+                let side = match random(2) {
+                    0 => OrderSide::Bid,
+                    _ => OrderSide::Ask,
+                };
+
+                // prices in sevenths, so quote amounts are rarely whole
+                let price = ((3 + random(12)) << 32) / 7;
+                let size = 1 + random(500) as u128;
+
+                // calls only fail on orders which are already gone, or on trading with oneself
+                match random(5) {
+                    0 if !orders.is_empty() => {
+                        let order = orders.swap_remove(random(orders.len() as u64) as usize);
+
+                        match market.try_cancel_order(&order) {
+                            Ok(Ok(())) => cancelled += 1,
+                            result => {
+                                assert_eq!(Err(Ok(DexMarketError::OrderNotFound)), result)
+                            }
+                        }
+                    }
+
+                    1 => match market.try_swap_exact_in(&owner, &side, &(size as i128), &0) {
+                        Ok(Ok(_)) => filled += 1,
+                        result => assert!(matches!(
+                            result,
+                            Err(Ok(DexMarketError::CannotSelfTrade
+                                | DexMarketError::InsufficientLiquidity))
+                        )),
+                    },
+
+                    funding => {
+                        let funding = match funding {
+                            2 => Funding::Vault,
+                            3 => Funding::Allowance,
+                            _ => Funding::Wallet,
+                        };
+
+                        let result = market.try_place_order(&OrderParams {
+                            side,
+                            size,
+                            price,
+                            owner,
+                            funding,
+                            referrer: Referrer::None,
+                        });
+
+                        match result {
+                            Ok(Ok((order, _))) => {
+                                placed += 1;
+
+                                // ids are reused once their price level empties
+                                if let Some(order) = order.filter(|order| !orders.contains(order)) {
+                                    orders.push(order);
+                                }
+                            }
+                            result => assert_eq!(Err(Ok(DexMarketError::CannotSelfTrade)), result),
+                        }
+                    }
+                }
+
+                assert_solvent(&orders);
+            }
+
+            assert!(placed > 0 && filled > 0 && cancelled > 0);
+
+            // once everything is returned, paid out and swept, nothing is left in the market
+            for order in &orders {
+                if market.try_get_order(order).is_ok() {
+                    market.cancel_order(order);
+                }
+            }
+
+            assert_solvent(&orders);
+
+            for user in &users {
+                let balance = market.balance(user);
+
+                assert_eq!(0, balance.base_locked);
+                assert_eq!(0, balance.quote_locked);
+
+                market.withdraw(user, &balance.base_free, &balance.quote_free);
+                market.claim(user);
+            }
+
+            market.withdraw_fees(&ctx.fee_authority);
+            market.sweep_dust(&ctx.fee_authority);
+
+            assert_eq!(0, ctx.base_client().balance(&ctx.market));
+            assert_eq!(0, ctx.quote_client().balance(&ctx.market));
+
+            for token in [ctx.base_client(), ctx.quote_client()] {
+                let total = users.iter().map(|user| token.balance(user)).sum::<i128>()
+                    + token.balance(&ctx.fee_authority);
+
+                assert_eq!(4_000_000, total);
+            }
+        }
+    }
+
+    #[test]
+    fn test_order_bounds() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        let order = |side, size, price| OrderParams {
+            side,
+            size,
+            price,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        assert_eq!(
+            Err(Ok(DexMarketError::AmountTooLarge)),
+            market.try_place_order(&order(OrderSide::Ask, MAX_ORDER_SIZE + 1, 1 << 32))
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidPrice)),
+            market.try_place_order(&order(OrderSide::Ask, 1, orderbook::MAX_PRICE + 1))
+        );
+
+        // the largest order fits when the tokens have the same decimals
+        ctx.base_client()
+            .mint(&ctx.users[0], &(MAX_ORDER_SIZE as i128));
+        market.place_order(&order(OrderSide::Ask, MAX_ORDER_SIZE, orderbook::MAX_PRICE));
+
+        assert_eq!(
+            FillQuote {
+                amount_in: (orderbook::MAX_PRICE as u128 * MAX_ORDER_SIZE).div_ceil(1 << 32)
+                    as i128,
+                amount_out: MAX_ORDER_SIZE as i128,
+            },
+            market.quote_fill(&OrderSide::Bid, &i128::MAX, &i128::MAX)
+        );
+
+        // but its value doesn't when the quote token has 7 more decimals
+        let asset = ctx.env.register_stellar_asset_contract(ctx.admin.clone());
+        let market = DexMarketContractClient::new(
+            &ctx.env,
+            &ctx.env.register_contract(None, DexMarketContract),
+        );
+
+        market.init(
+            &ctx.admin,
+            &DexMarketInfo {
+                base_token: ctx.base_token.clone(),
+                quote_token: asset,
+                base_min_order_size: 1,
+                price_tick: 1,
+                fee_authority: ctx.fee_authority.clone(),
+                maker_fee_bps: 0,
+                taker_fee_bps: 0,
+                base_decimals: 0,
+                quote_decimals: 0,
+            },
+        );
+
+        assert_eq!(
+            Err(Ok(DexMarketError::ArithmeticOverflow)),
+            market.try_place_order(&order(OrderSide::Bid, MAX_ORDER_SIZE, orderbook::MAX_PRICE))
+        );
+
+        ctx.base_client()
+            .mint(&ctx.users[1], &(MAX_ORDER_SIZE as i128));
+        market.place_order(&OrderParams {
+            owner: ctx.users[1].clone(),
+            ..order(OrderSide::Ask, MAX_ORDER_SIZE, orderbook::MAX_PRICE)
+        });
+
+        assert_eq!(
+            Err(Ok(DexMarketError::ArithmeticOverflow)),
+            market.try_quote_fill(&OrderSide::Bid, &i128::MAX, &i128::MAX)
+        );
+    }
+
+    #[test]
+    fn test_cancel_malformed_order_id() {
+        use soroban_sdk::{vec, BytesN, TryFromVal};
+
+        let ctx = TestEnv::new();
+
+        let market = ctx.market_client();
+
+        ctx.env.mock_all_auths();
+
+        let mut bytes = [0u8; 16];
+        bytes[0..2].copy_from_slice(&ORDER_BOOK_PREFIX.to_be_bytes());
+        bytes[3] = 0xFF;
+
+        let raw = vec![&ctx.env, BytesN::from_array(&ctx.env, &bytes)];
+        let order = OrderId::try_from_val(&ctx.env, &raw.to_val()).unwrap();
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidOrderId)),
+            market.try_cancel_order(&order)
+        );
+    }
+
+    #[test]
+    fn test_cancel_missing_order() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &2_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+
+        let ask = OrderParams {
+            side: OrderSide::Ask,
+            size: 1_000,
+            price: 1 << 32,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        let (cancelled_id, _) = market.place_order(&ask);
+        let cancelled_id = cancelled_id.unwrap();
+        market.cancel_order(&cancelled_id);
+
+        let (filled_id, _) = market.place_order(&ask);
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            owner: ctx.users[1].clone(),
+            ..ask
+        });
+
+        for order in [cancelled_id, filled_id.unwrap()] {
+            assert_eq!(
+                Err(Ok(DexMarketError::OrderNotFound)),
+                market.try_cancel_order(&order)
+            );
+        }
+
+        assert_eq!(1_000, ctx.base_client().balance(&ctx.users[0]));
+    }
+
+    #[test]
+    fn test_insufficient_wallet_balance() {
         let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.quote_client().mint(&ctx.users[0], &999);
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientBalance)),
+            market.try_place_order(&OrderParams {
+                side: OrderSide::Bid,
+                size: 1_000,
+                price: 1 << 32,
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                referrer: Referrer::None,
+            })
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientBalance)),
+            market.try_deposit(&ctx.users[0], &0, &1_000)
+        );
+        assert_eq!(999, ctx.quote_client().balance(&ctx.users[0]));
+    }
 
+    #[test]
+    fn test_queries() {
+        let ctx = TestEnv::new();
         let market = ctx.market_client();
 
-        ctx.env.mock_all_auths();
+        assert_eq!(ctx.base_token, market.market_info().base_token);
+        assert_eq!((None, None), market.best_bid_ask());
+        assert_eq!(None, market.spread());
+        assert_eq!(None, market.mid_price());
 
         ctx.base_client().mint(&ctx.users[0], &1_000);
-        ctx.quote_client().mint(&ctx.users[1], &3_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
 
-        for i in 1..5 {
-            let _ = market
-                .place_order(&OrderParams {
-                    side: OrderSide::Ask,
-                    price: (i << 32),
-                    size: 100 * i as u128,
-                    owner: ctx.users[0].clone(),
-                })
-                .unwrap();
+        let order = |side, owner: &Address, price| OrderParams {
+            side,
+            size: 200,
+            price,
+            owner: owner.clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        market.place_order(&order(OrderSide::Bid, &ctx.users[1], 1 << 32));
+        market.place_order(&order(OrderSide::Bid, &ctx.users[1], 2 << 32));
+        let (ask_id, _) = market.place_order(&order(OrderSide::Ask, &ctx.users[0], 4 << 32));
+        market.place_order(&order(OrderSide::Ask, &ctx.users[0], 5 << 32));
+
+        assert_eq!((Some(2 << 32), Some(4 << 32)), market.best_bid_ask());
+        assert_eq!(Some(2 << 32), market.spread());
+        assert_eq!(Some(3 << 32), market.mid_price());
+
+        // the best bid is filled by an ask, and what's left of that ask becomes the best ask
+        market.place_order(&OrderParams {
+            size: 300,
+            ..order(OrderSide::Ask, &ctx.users[0], 2 << 32)
+        });
+        market.place_order(&OrderParams {
+            size: 50,
+            ..order(OrderSide::Bid, &ctx.users[1], 4 << 32)
+        });
+
+        assert_eq!((Some(1 << 32), Some(2 << 32)), market.best_bid_ask());
+        assert_eq!(Some(1 << 32), market.spread());
+        assert_eq!(Some(3 << 31), market.mid_price());
+
+        let ask_id = ask_id.unwrap();
+        assert_eq!(
+            OrderInfo {
+                id: ask_id.clone(),
+                owner: ctx.users[0].clone(),
+                funding: Funding::Wallet,
+                side: OrderSide::Ask,
+                price: 4 << 32,
+                remaining_size: 200,
+                pending_events: Vec::new(&ctx.env),
+            },
+            market.get_order(&ask_id)
+        );
+
+        market.cancel_order(&ask_id);
+        assert_eq!(
+            Err(Ok(DexMarketError::OrderNotFound)),
+            market.try_get_order(&ask_id)
+        );
+    }
+
+    #[test]
+    fn test_delegates() {
+        use soroban_sdk::testutils::{Address as _, Ledger};
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+        let owner = &ctx.users[0];
+        let delegate = Address::random(&ctx.env);
+
+        ctx.quote_client().mint(owner, &1_000);
+        market.deposit(owner, &0, &1_000);
+
+        let bid = |price, funding| OrderParams {
+            side: OrderSide::Bid,
+            size: 100,
+            price,
+            owner: owner.clone(),
+            funding,
+            referrer: Referrer::None,
+        };
+
+        let mut permissions = DelegatePermissions {
+            place: true,
+            cancel: true,
+            cancel_all: false,
+            max_notional: 200,
+            expiration_ledger: 10,
+        };
+
+        assert_eq!(
+            Err(Ok(DexMarketError::Unauthorized)),
+            market.try_place_order_as(&delegate, &bid(1 << 32, Funding::Vault))
+        );
+
+        market.grant_delegate(owner, &delegate, &permissions);
+        assert_eq!(
+            Some(permissions.clone()),
+            market.delegate_permissions(owner, &delegate)
+        );
+
+        // the delegate authorizes orders itself, and they are funded by the owner
+        let (first_id, _) = market.place_order_as(&delegate, &bid(1 << 32, Funding::Vault));
+        let first_id = first_id.unwrap();
+        assert_eq!(delegate, ctx.env.auths()[0].0);
+        assert_eq!(owner.clone(), market.get_order(&first_id).owner);
+
+        let (second_id, _) = market.place_order_as(&delegate, &bid(2 << 32, Funding::Vault));
+        let second_id = second_id.unwrap();
+
+        // the wallet can't be used without the owner, and the notional is limited
+        assert_eq!(
+            Err(Ok(DexMarketError::Unauthorized)),
+            market.try_place_order_as(&delegate, &bid(1 << 32, Funding::Wallet))
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::Unauthorized)),
+            market.try_place_order_as(&delegate, &bid(3 << 32, Funding::Vault))
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::Unauthorized)),
+            market.try_cancel_all_orders_as(&delegate, owner, &10)
+        );
+
+        market.cancel_order_as(&delegate, &first_id);
+        assert_eq!(800, market.balance(owner).quote_free);
+
+        permissions.cancel_all = true;
+        market.grant_delegate(owner, &delegate, &permissions);
+        market.place_order(&bid(1 << 32, Funding::Vault));
+
+        // the oldest order is cancelled first
+        assert_eq!(1, market.cancel_all_orders_as(&delegate, owner, &1));
+        assert_eq!(
+            Err(Ok(DexMarketError::OrderNotFound)),
+            market.try_get_order(&second_id)
+        );
+        assert_eq!(1, market.cancel_all_orders(owner, &10));
+        assert_eq!(1_000, market.balance(owner).quote_free);
+
+        // permissions stop working after their expiration ledger, or once they are revoked
+        ctx.env
+            .ledger()
+            .with_mut(|ledger| ledger.sequence_number = 11);
+        assert_eq!(None, market.delegate_permissions(owner, &delegate));
+        assert_eq!(
+            Err(Ok(DexMarketError::Unauthorized)),
+            market.try_place_order_as(&delegate, &bid(1 << 32, Funding::Vault))
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidExpiration)),
+            market.try_grant_delegate(owner, &delegate, &permissions)
+        );
+
+        permissions.expiration_ledger = 20;
+        market.grant_delegate(owner, &delegate, &permissions);
+        market.revoke_delegate(owner, &delegate);
+        assert_eq!(
+            Err(Ok(DexMarketError::Unauthorized)),
+            market.try_place_order_as(&delegate, &bid(1 << 32, Funding::Vault))
+        );
+        assert_eq!(1_000, market.balance(owner).quote_free);
+    }
+
+    #[test]
+    fn test_cancel_all_orders() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+        let (owner, other) = (&ctx.users[0], &ctx.users[1]);
+
+        ctx.base_client().mint(owner, &400);
+        ctx.quote_client().mint(other, &10_000);
+
+        let ask = |owner: &Address, price| OrderParams {
+            side: OrderSide::Ask,
+            size: 100,
+            price,
+            owner: owner.clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        // the book is deep with someone else's orders
+        for price in 1..=10 {
+            market.place_order(&OrderParams {
+                side: OrderSide::Bid,
+                ..ask(other, price << 32)
+            });
         }
 
+        let ids: std::vec::Vec<_> = (0..4)
+            .map(|_| market.place_order(&ask(owner, 11 << 32)).0.unwrap())
+            .collect();
+
+        assert_eq!(
+            soroban_sdk::vec![&ctx.env, ids[0].clone(), ids[1].clone()],
+            market.owner_orders(owner, &None, &2)
+        );
+        assert_eq!(
+            soroban_sdk::vec![&ctx.env, ids[2].clone(), ids[3].clone()],
+            market.owner_orders(owner, &Some(ids[1].clone()), &2)
+        );
+
+        // a filled order is no longer the owner's to cancel
         market.place_order(&OrderParams {
             side: OrderSide::Bid,
+            ..ask(other, 11 << 32)
+        });
+        assert_eq!(
+            soroban_sdk::vec![&ctx.env, ids[1].clone(), ids[2].clone(), ids[3].clone()],
+            market.owner_orders(owner, &None, &10)
+        );
+
+        // nor is one cancelled from the middle of the list
+        market.cancel_order(&ids[2]);
+        assert_eq!(
+            soroban_sdk::vec![&ctx.env, ids[1].clone(), ids[3].clone()],
+            market.owner_orders(owner, &None, &10)
+        );
+
+        assert_eq!(1, market.cancel_all_orders(owner, &1));
+        assert_eq!(
+            soroban_sdk::vec![&ctx.env, ids[3].clone()],
+            market.owner_orders(owner, &None, &10)
+        );
+
+        assert_eq!(1, market.cancel_all_orders(owner, &10));
+        assert_eq!(0, market.cancel_all_orders(owner, &10));
+        assert!(market.owner_orders(owner, &None, &10).is_empty());
+        assert_eq!(300, ctx.base_client().balance(owner));
+    }
+
+    #[test]
+    fn test_failing_token_payout() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+        market.deposit(&ctx.users[1], &0, &1_000);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Ask,
             size: 1_000,
-            price: (3 << 32),
+            price: (1 << 32),
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+
+        // the taker pays from its internal balance, so only the payout to the maker fails
+        ctx.quote_client().set_failing(&true);
+
+        market.place_order(&OrderParams {
+            side: OrderSide::Bid,
+            size: 400,
+            price: (1 << 32),
             owner: ctx.users[1].clone(),
+            funding: Funding::Vault,
+            referrer: Referrer::None,
         });
 
-        let balance_0_quote = ctx.quote_client().balance(&ctx.users[0]);
-        let balance_1_base = ctx.base_client().balance(&ctx.users[1]);
+        assert_eq!(400, market.balance(&ctx.users[1]).base_free);
+        assert_eq!(400, market.unsettled(&ctx.users[0]).quote);
+        assert!(market.try_claim(&ctx.users[0]).is_err());
 
-        let balance_0_base = ctx.base_client().balance(&ctx.users[0]);
-        let balance_1_quote = ctx.quote_client().balance(&ctx.users[1]);
+        ctx.quote_client().set_failing(&false);
+        market.claim(&ctx.users[0]);
 
-        assert_eq!(0, balance_0_base);
-        assert_eq!(600, balance_1_base);
+        assert_eq!(400, ctx.quote_client().balance(&ctx.users[0]));
+        assert_eq!(UnsettledFunds::default(), market.unsettled(&ctx.users[0]));
+    }
 
-        assert_eq!(1_400, balance_0_quote);
-        assert_eq!(4_00, balance_1_quote);
+    #[test]
+    fn test_allowance_funding() {
+        use soroban_sdk::testutils::Ledger;
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+        let owner = &ctx.users[0];
+
+        ctx.quote_client().mint(owner, &1_000);
+        ctx.quote_client().approve(owner, &ctx.market, &500, &10);
+
+        let bid = |size| OrderParams {
+            side: OrderSide::Bid,
+            size,
+            price: 1 << 32,
+            owner: owner.clone(),
+            funding: Funding::Allowance,
+            referrer: Referrer::None,
+        };
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientAllowance)),
+            market.try_place_order(&bid(600))
+        );
+
+        // the owner only authorizes the order, and the market pulls the tokens itself
+        let (order_id, _) = market.place_order(&bid(400));
+        let auths = ctx.env.auths();
+
+        assert_eq!(1, auths.len());
+        assert_eq!(*owner, auths[0].0);
+        assert!(auths[0].1.sub_invocations.is_empty());
+        assert_eq!(600, ctx.quote_client().balance(owner));
+        assert_eq!(100, ctx.quote_client().allowance(owner, &ctx.market));
+
+        // refunds and proceeds go back to the owner's account
+        market.cancel_order(&order_id.unwrap());
+        assert_eq!(1_000, ctx.quote_client().balance(owner));
+        assert_eq!(VaultBalance::default(), market.balance(owner));
+
+        ctx.env
+            .ledger()
+            .with_mut(|ledger| ledger.sequence_number = 11);
+        assert_eq!(
+            Err(Ok(DexMarketError::InsufficientAllowance)),
+            market.try_place_order(&bid(50))
+        );
+    }
+
+    #[test]
+    fn test_upgrade_populated_market() {
+        let ctx = TestEnv::with_fees(0, 10);
+        let market = ctx.market_client();
+
+        // the code is the same before and after, but the upgrade has to point at an uploaded wasm
+        ctx.env.budget().reset_unlimited();
+        let wasm_hash = ctx
+            .env
+            .deployer()
+            .upload_contract_wasm(dex_market_client::WASM);
+
+        ctx.base_client().mint(&ctx.users[0], &1_000);
+        ctx.quote_client().mint(&ctx.users[1], &1_000);
+        market.deposit(&ctx.users[1], &0, &500);
+
+        let (ask_id, _) = market.place_order(&OrderParams {
+            side: OrderSide::Ask,
+            size: 300,
+            price: 1 << 32,
+            owner: ctx.users[0].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        });
+        let ask_id = ask_id.unwrap();
+
+        let bid = OrderParams {
+            side: OrderSide::Bid,
+            size: 100,
+            price: 1 << 32,
+            owner: ctx.users[1].clone(),
+            funding: Funding::Vault,
+            referrer: Referrer::None,
+        };
+        market.place_order(&bid);
+
+        let info = market.market_info();
+
+        market.upgrade(&wasm_hash);
+        assert_eq!(ctx.admin, ctx.env.auths()[0].0);
+
+        // the layout didn't change, so the market is usable straight away
+        assert_eq!(migration::CURRENT_SCHEMA_VERSION, market.schema_version());
+        assert!(!market.migrate());
+        assert_eq!(info, market.market_info());
+
+        market.place_order(&bid);
+
+        assert_eq!(100, market.get_order(&ask_id).remaining_size);
+        assert_eq!(200, ctx.quote_client().balance(&ctx.users[0]));
+
+        let balance = market.balance(&ctx.users[1]);
+        assert_eq!((198, 300), (balance.base_free, balance.quote_free));
+    }
+
+    #[test]
+    fn test_schema_version() {
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        assert_eq!(migration::CURRENT_SCHEMA_VERSION, market.schema_version());
+        assert!(!market.migrate());
+        assert!(market.migrate_orders(&1));
+
+        // storage written by a newer version can't be read
+        ctx.env.as_contract(&ctx.market, || {
+            migration::set_schema_version(&ctx.env, migration::CURRENT_SCHEMA_VERSION + 1);
+        });
+
+        assert_eq!(
+            Err(Ok(DexMarketError::UnsupportedSchemaVersion)),
+            market.try_market_info()
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::UnsupportedSchemaVersion)),
+            market.try_migrate()
+        );
+    }
+
+    /// The interface of the market as it was deployed at version 0 of the storage layout
+    mod v0 {
+        use soroban_sdk::{contractclient, contracttype, Address, Env};
+
+        use crate::{migration::LegacyDexMarketInfo, OrderId, OrderSide};
+
+        pub const WASM: &[u8] = include_bytes!("../fixtures/dex_market_v0.wasm");
+
+        #[contracttype(export = false)]
+        pub struct OrderParams {
+            pub side: OrderSide,
+            pub size: u128,
+            pub price: u64,
+            pub owner: Address,
+        }
+
+        #[contractclient(name = "Client")]
+        #[allow(dead_code)]
+        pub trait DexMarket {
+            fn init(env: Env, info: LegacyDexMarketInfo);
+            fn place_order(env: Env, params: OrderParams) -> Option<OrderId>;
+        }
+    }
+
+    #[test]
+    fn test_upgrade_v0_market() {
+        use soroban_sdk::testutils::Address as _;
+
+        let env = Env::default();
+        env.mock_all_auths();
+        env.budget().reset_unlimited();
+
+        let admin = Address::random(&env);
+        let users = [Address::random(&env), Address::random(&env)];
+
+        let new_token = |decimals: u32| {
+            let token = env.register_contract(None, test_token::Token);
+            let symbol = soroban_sdk::String::from_slice(&env, "TOKEN");
+            test_token::TokenClient::new(&env, &token)
+                .initialize(&admin, &decimals, &symbol, &symbol);
+
+            test_token::TokenClient::new(&env, &token)
+        };
+
+        let base = new_token(1);
+        let quote = new_token(0);
+        base.mint(&users[0], &100);
+        quote.mint(&users[1], &200);
+
+        let market = env.register_contract_wasm(None, v0::WASM);
+        let legacy = v0::Client::new(&env, &market);
+
+        legacy.init(&migration::LegacyDexMarketInfo {
+            base_token: base.address.clone(),
+            quote_token: quote.address.clone(),
+            base_min_order_size: 1,
+        });
+
+        // prices were in the smallest units of each token, so an ask at 1 is 10 quote tokens
+        // for a whole base token, which has one decimal
+        let legacy_order = |side, size, price, owner: &Address| {
+            legacy.place_order(&v0::OrderParams {
+                side,
+                size,
+                price,
+                owner: owner.clone(),
+            })
+        };
+
+        legacy_order(OrderSide::Ask, 100, 1 << 32, &users[0]);
+        legacy_order(OrderSide::Bid, 50, 1 << 31, &users[1]);
+
+        // this bid was held 1, where 2 would be needed now that the escrow is rounded up
+        legacy_order(OrderSide::Bid, 3, 1 << 31, &users[1]);
+        assert_eq!(174, quote.balance(&users[1]));
+
+        // version 0 had no upgrade entrypoint, so its code is replaced directly
+        let wasm_hash = env.deployer().upload_contract_wasm(dex_market_client::WASM);
+        env.as_contract(&market, || {
+            env.deployer().update_current_contract_wasm(wasm_hash);
+        });
+
+        let market = DexMarketContractClient::new(&env, &market);
+        let bid = OrderParams {
+            side: OrderSide::Bid,
+            size: 10,
+            price: 10 << 32,
+            owner: users[1].clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        assert_eq!(0, market.schema_version());
+        assert_eq!(
+            Err(Ok(DexMarketError::MigrationRequired)),
+            market.try_market_info()
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::MigrationRequired)),
+            market.try_migrate_orders(&10)
+        );
+
+        // nobody upgraded the market, so nobody can take it over by migrating it
+        assert_eq!(Err(Ok(DexMarketError::Unauthorized)), market.try_migrate());
+
+        // record the upgrader the way `upgrade` would have
+        env.as_contract(&market.address, || migration::set_migrator(&env, &admin));
+
+        assert!(market.migrate());
+        assert_eq!(admin, env.auths()[0].0);
+        assert!(!market.migrate());
+
+        // trading stays blocked until the orders are moved
+        assert_eq!(
+            Err(Ok(DexMarketError::MigrationRequired)),
+            market.try_place_order(&bid)
+        );
+        assert!(!market.migrate_orders(&1));
+        assert!(market.migrate_orders(&10));
+        assert!(market.migrate_orders(&10));
+
+        assert_eq!(migration::CURRENT_SCHEMA_VERSION, market.schema_version());
+        assert_eq!(
+            DexMarketInfo {
+                base_token: base.address.clone(),
+                quote_token: quote.address.clone(),
+                base_min_order_size: 1,
+                price_tick: 1,
+                fee_authority: admin.clone(),
+                maker_fee_bps: 0,
+                taker_fee_bps: 0,
+                base_decimals: 1,
+                quote_decimals: 0,
+            },
+            market.market_info()
+        );
+        assert_eq!(MarketStatus::Active, market.status());
+        assert_eq!(175, quote.balance(&users[1]));
+
+        let ask_id = market.owner_orders(&users[0], &None, &10).get(0).unwrap();
+        let bid_ids = market.owner_orders(&users[1], &None, &10);
+        assert_eq!(1, bid_ids.len());
+
+        let ask = market.get_order(&ask_id);
+        let bid_id = bid_ids.get(0).unwrap();
+        assert_eq!((10 << 32, 100), (ask.price, ask.remaining_size));
+        let bid = market.get_order(&bid_id);
+        assert_eq!((5 << 32, 50), (bid.price, bid.remaining_size));
+
+        // the moved orders can be filled and cancelled like any other
+        assert_eq!(
+            (100, 100),
+            market.swap_exact_in(&users[1], &OrderSide::Bid, &100, &100)
+        );
+        assert_eq!(100, quote.balance(&users[0]));
+        assert_eq!(100, base.balance(&users[1]));
+        assert!(market.owner_orders(&users[0], &None, &10).is_empty());
+
+        market.cancel_order(&bid_id);
+        assert_eq!(100, quote.balance(&users[1]));
+
+        assert_eq!(0, base.balance(&market.address));
+        assert_eq!(0, quote.balance(&market.address));
+    }
+
+    #[test]
+    fn test_upgrade_requires_admin() {
+        use soroban_sdk::{
+            testutils::{MockAuth, MockAuthInvoke},
+            IntoVal,
+        };
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+
+        ctx.env.budget().reset_unlimited();
+        let wasm_hash = ctx
+            .env
+            .deployer()
+            .upload_contract_wasm(dex_market_client::WASM);
+
+        ctx.env.mock_auths(&[MockAuth {
+            address: &ctx.users[0],
+            invoke: &MockAuthInvoke {
+                contract: &ctx.market,
+                fn_name: "upgrade",
+                args: (wasm_hash.clone(),).into_val(&ctx.env),
+                sub_invokes: &[],
+            },
+        }]);
+
+        assert!(market.try_upgrade(&wasm_hash).is_err());
+        assert!(ctx.env.auths().is_empty());
+    }
+
+    #[test]
+    fn test_referral_fees() {
+        use soroban_sdk::testutils::Address as _;
+
+        let ctx = TestEnv::with_fees(0, 100);
+        let market = ctx.market_client();
+        let referrer = Address::random(&ctx.env);
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidFeeSchedule)),
+            market.try_set_referral_share(&10_001)
+        );
+
+        market.set_referral_share(&2_000);
+        assert_eq!(2_000, market.referral_share());
+
+        ctx.base_client().mint(&ctx.users[0], &1_500);
+        ctx.quote_client().mint(&ctx.users[1], &1_500);
+
+        let order = |side, size, owner: &Address, referrer| OrderParams {
+            side,
+            size,
+            price: 1 << 32,
+            owner: owner.clone(),
+            funding: Funding::Wallet,
+            referrer,
+        };
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidReferrer)),
+            market.try_place_order(&order(
+                OrderSide::Ask,
+                1_000,
+                &ctx.users[0],
+                Referrer::Address(ctx.users[0].clone()),
+            ))
+        );
+
+        // the referrer earns a share of the taker fee, in the token the taker receives
+        market.place_order(&order(OrderSide::Ask, 1_000, &ctx.users[0], Referrer::None));
+        let (_, summary) = market.place_order(&order(
+            OrderSide::Bid,
+            1_000,
+            &ctx.users[1],
+            Referrer::Address(referrer.clone()),
+        ));
+
+        assert_eq!((10, 8), (summary.taker_fee, summary.base_fees));
+
+        market.place_order(&order(OrderSide::Bid, 500, &ctx.users[1], Referrer::None));
+        market.place_order(&order(
+            OrderSide::Ask,
+            500,
+            &ctx.users[0],
+            Referrer::Address(referrer.clone()),
+        ));
+
+        let earnings = ReferralEarnings { base: 2, quote: 1 };
+        assert_eq!(earnings, market.referral_earnings(&referrer));
+
+        assert_eq!(earnings, market.claim_referral_fees(&referrer));
+        assert_eq!(referrer, ctx.env.auths()[0].0);
+        assert_eq!(
+            ReferralEarnings::default(),
+            market.referral_earnings(&referrer)
+        );
+        assert_eq!(2, ctx.base_client().balance(&referrer));
+        assert_eq!(1, ctx.quote_client().balance(&referrer));
+
+        // the market keeps the rest of the fees, and nothing else
+        market.withdraw_fees(&ctx.fee_authority);
+        assert_eq!(8, ctx.base_client().balance(&ctx.fee_authority));
+        assert_eq!(4, ctx.quote_client().balance(&ctx.fee_authority));
+        assert_eq!(0, ctx.base_client().balance(&ctx.market));
+        assert_eq!(0, ctx.quote_client().balance(&ctx.market));
+    }
+
+    #[test]
+    fn test_liquidity_rewards() {
+        use soroban_sdk::testutils::Ledger;
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+        let set_time = |timestamp| {
+            ctx.env
+                .ledger()
+                .with_mut(|ledger| ledger.timestamp = timestamp)
+        };
+
+        let reward_token = ctx.env.register_contract(None, test_token::Token);
+        let reward_client = test_token::TokenClient::new(&ctx.env, &reward_token);
+        let symbol = soroban_sdk::String::from_slice(&ctx.env, "RWD");
+        reward_client.initialize(&ctx.admin, &0, &symbol, &symbol);
+        reward_client.mint(&ctx.admin, &10_000);
+
+        let config = RewardConfig {
+            token: reward_token.clone(),
+            emission_rate: 10,
+            band_bps: 2_000,
+            min_order_size: 1,
+        };
+
+        set_time(0);
+        assert_eq!(
+            Err(Ok(DexMarketError::RewardsNotConfigured)),
+            market.try_fund_rewards(&ctx.admin, &10_000)
+        );
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidRewardConfig)),
+            market.try_set_reward_config(&RewardConfig {
+                band_bps: 0,
+                ..config.clone()
+            })
+        );
+
+        market.set_reward_config(&config);
+        market.fund_rewards(&ctx.admin, &10_000);
+        assert_eq!(Some(config.clone()), market.reward_config());
+        assert_eq!(10_000, market.reward_pool());
+
+        ctx.quote_client().mint(&ctx.users[0], &1_400);
+        ctx.base_client().mint(&ctx.users[1], &300);
+
+        let order = |side, size, price, owner: &Address| OrderParams {
+            side,
+            size,
+            price,
+            owner: owner.clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        // both orders are 10% from the mid price, and the last one is outside the band
+        market.place_order(&order(OrderSide::Bid, 100, 9 << 32, &ctx.users[0]));
+        market.place_order(&order(OrderSide::Ask, 300, 11 << 32, &ctx.users[1]));
+        market.place_order(&order(OrderSide::Bid, 100, 5 << 32, &ctx.users[0]));
+
+        set_time(100);
+        assert_eq!(250, market.claim_rewards(&ctx.users[0]));
+        assert_eq!(250, reward_client.balance(&ctx.users[0]));
+        assert_eq!(750, market.rewards(&ctx.users[1]));
+        assert_eq!(9_000, market.reward_pool());
+
+        // nothing is emitted while the market is halted
+        market.set_status(&MarketStatus::Halted);
+        set_time(200);
+        market.set_status(&MarketStatus::Active);
+        assert_eq!(750, market.rewards(&ctx.users[1]));
+
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidRewardConfig)),
+            market.try_set_reward_config(&RewardConfig {
+                token: ctx.base_token.clone(),
+                ..config
+            })
+        );
+
+        // emission stops once the funded rewards run out
+        set_time(10_000);
+        assert_eq!(7_500, market.claim_rewards(&ctx.users[1]));
+        assert_eq!(2_250, market.claim_rewards(&ctx.users[0]));
+        assert_eq!(0, market.reward_pool());
+        assert_eq!(0, reward_client.balance(&ctx.market));
+    }
+
+    #[test]
+    fn test_liquidity_rewards_read_limit() {
+        use soroban_sdk::testutils::{Address as _, Ledger};
+
+        let ctx = TestEnv::new();
+        let market = ctx.market_client();
+        let latecomer = Address::random(&ctx.env);
+        let set_time = |timestamp| {
+            ctx.env
+                .ledger()
+                .with_mut(|ledger| ledger.timestamp = timestamp)
+        };
+
+        let reward_token = ctx.env.register_contract(None, test_token::Token);
+        let reward_client = test_token::TokenClient::new(&ctx.env, &reward_token);
+        let symbol = soroban_sdk::String::from_slice(&ctx.env, "RWD");
+        reward_client.initialize(&ctx.admin, &0, &symbol, &symbol);
+        reward_client.mint(&ctx.admin, &10_000);
+
+        let config = RewardConfig {
+            token: reward_token,
+            emission_rate: 10,
+            band_bps: 10_000,
+            min_order_size: 10,
+        };
+
+        set_time(0);
+        assert_eq!(
+            Err(Ok(DexMarketError::InvalidRewardConfig)),
+            market.try_set_reward_config(&RewardConfig {
+                min_order_size: 0,
+                ..config.clone()
+            })
+        );
+        market.set_reward_config(&config);
+        market.fund_rewards(&ctx.admin, &10_000);
+
+        ctx.quote_client().mint(&ctx.users[0], &100_000);
+        ctx.quote_client().mint(&latecomer, &100);
+        ctx.base_client().mint(&ctx.users[1], &100);
+
+        let order = |side, size, price, owner: &Address| OrderParams {
+            side,
+            size,
+            price,
+            owner: owner.clone(),
+            funding: Funding::Wallet,
+            referrer: Referrer::None,
+        };
+
+        // an order below the minimum size, ahead of enough bids to fill the orders read
+        market.place_order(&order(OrderSide::Bid, 5, 9 << 32, &ctx.users[0]));
+        for _ in 1..rewards::MAX_WEIGHED_ORDERS {
+            market.place_order(&order(OrderSide::Bid, 10, 9 << 32, &ctx.users[0]));
+        }
+        market.place_order(&order(OrderSide::Bid, 10, 9 << 32, &latecomer));
+
+        // nothing is emitted while there's no mid price
+        set_time(100);
+        market.place_order(&order(OrderSide::Ask, 100, 11 << 32, &ctx.users[1]));
+        assert_eq!(0, market.rewards(&ctx.users[0]));
+        assert_eq!(10_000, market.reward_pool());
+
+        // with the mid price at 10, the ask weighs 100 * 9_000, the bids read 19 * 10 * 9_000,
+        // and the last bid isn't read
+        set_time(225);
+        assert_eq!(818, market.claim_rewards(&ctx.users[0]));
+        assert_eq!(431, market.claim_rewards(&ctx.users[1]));
+        assert_eq!(0, market.claim_rewards(&latecomer));
+
+        // filling the best price with orders too small to earn anything doesn't make accruing
+        // read more orders
+        let accrual_cost = |dust_orders: u32, timestamp| {
+            ctx.env.budget().reset_unlimited();
+            for _ in 0..dust_orders {
+                market.place_order(&order(OrderSide::Bid, 1, 19 << 31, &ctx.users[0]));
+            }
+
+            set_time(timestamp);
+            ctx.env.budget().reset_default();
+            market.claim_rewards(&ctx.users[0]);
+
+            ctx.env.budget().cpu_instruction_cost()
+        };
+
+        let cost = accrual_cost(rewards::MAX_WEIGHED_ORDERS, 300);
+        let more_dust_cost = accrual_cost(10 * rewards::MAX_WEIGHED_ORDERS, 400);
+
+        // only decoding the larger price level costs more
+        assert!(more_dust_cost < 2 * cost);
     }
 }
@@ -0,0 +1,267 @@
+//! Versions of the storage layout, and the migrations between them
+//!
+//! Markets deployed before the layout was versioned are at version 0. Their market info only
+//! holds the tokens and the minimum order size, they have no admin, fees or trading status, and
+//! their orders only record their owner. Their prices are the number of the smallest units of the
+//! quote token paid for one of the smallest units of the base token, where they're now in whole
+//! tokens.
+//!
+//! A version 0 market is migrated in two steps, and can't be used until both are done:
+//!
+//! - `migrate` rewrites the market info with the decimals of the tokens and a price tick of 1,
+//!   makes the admin who upgraded the market its admin and fee authority, and leaves the fees at
+//!   zero and the market active.
+//! - `migrate_orders` moves the resting orders, a page at a time, from the old book into the
+//!   book of the current layout, which is kept under a different prefix. Each order is funded
+//!   from its owner's wallet, and gets a new id.
+//!
+//! An order is only moved if it can be carried over exactly: its price has to be representable
+//! in whole tokens, and a bid has to need the same escrow under the current rounding as the
+//! old version held for it. Any other order is cancelled and refunded the way the old version
+//! would have refunded it.
+//!
+//! Only the admin who upgraded a market can migrate it, so nobody else can make themselves the
+//! admin of a market from before admins existed. Version 0 has no `upgrade` entrypoint though,
+//! and only a contract can replace its own code, so a deployed version 0 market can't be brought
+//! to this code on-chain, and the migration from it is only reachable where the code is replaced
+//! directly, as in the tests.
+
+use orderbook::{OrderBook, OrderEntry, OrderSide};
+use soroban_sdk::{contracttype, symbol_short, token, Address, Env, Symbol};
+
+use crate::{
+    events::{self, OrderCancelledEvent},
+    funds::{Asset, Funds},
+    price, DexMarketError, DexMarketInfo, Funding, MarketStatus, OrderDetail, OrderId, MARKET_INFO,
+};
+
+/// Storage key for the version of the storage layout
+const SCHEMA_VERSION: Symbol = symbol_short!("SCHEMA");
+
+/// Storage key set while the orders of a version 0 market are being moved
+const MIGRATING_ORDERS: Symbol = symbol_short!("MIGORDERS");
+
+/// Storage key for the admin who last upgraded the market
+const MIGRATOR: Symbol = symbol_short!("MIGRATOR");
+
+/// The version of the storage layout used by this version of the contract
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// The prefix of the order book in version 0
+const LEGACY_ORDER_BOOK_PREFIX: u16 = 0xF1A0;
+
+/// The market configuration, as it was stored in version 0
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LegacyDexMarketInfo {
+    pub base_token: Address,
+    pub quote_token: Address,
+    pub base_min_order_size: u128,
+}
+
+/// The details of an order, as they were stored in version 0
+#[contracttype]
+struct LegacyOrderDetail {
+    owner: Address,
+}
+
+pub fn schema_version(env: &Env) -> u32 {
+    env.storage().instance().get(&SCHEMA_VERSION).unwrap_or(0)
+}
+
+pub fn set_schema_version(env: &Env, version: u32) {
+    env.storage().instance().set(&SCHEMA_VERSION, &version);
+}
+
+/// Record the admin upgrading the market, who is the one allowed to migrate it
+pub fn set_migrator(env: &Env, migrator: &Address) {
+    env.storage().instance().set(&MIGRATOR, migrator);
+}
+
+/// Check that the storage is in the layout used by this version of the contract
+pub fn check_schema_version(env: &Env) -> Result<(), DexMarketError> {
+    match schema_version(env) {
+        CURRENT_SCHEMA_VERSION => Ok(()),
+        version if version > CURRENT_SCHEMA_VERSION => {
+            Err(DexMarketError::UnsupportedSchemaVersion)
+        }
+        _ => Err(DexMarketError::MigrationRequired),
+    }
+}
+
+/// Migrate the configuration of a version 0 market, making the admin who upgraded it its admin
+///
+/// Returns true if anything was migrated. Storage written by a newer version of the contract
+/// can't be read, so a market can't be downgraded.
+pub fn migrate(env: &Env) -> Result<bool, DexMarketError> {
+    let version = schema_version(env);
+
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(DexMarketError::UnsupportedSchemaVersion);
+    }
+
+    if version == CURRENT_SCHEMA_VERSION || env.storage().instance().has(&MIGRATING_ORDERS) {
+        return Ok(false);
+    }
+
+    let migrator: Address = env
+        .storage()
+        .instance()
+        .get(&MIGRATOR)
+        .ok_or(DexMarketError::Unauthorized)?;
+
+    migrator.require_auth();
+    migrate_market_info(env, &migrator)?;
+
+    env.storage().instance().set(&MIGRATING_ORDERS, &());
+
+    Ok(true)
+}
+
+/// Move up to `max_orders` orders out of the version 0 book, cancelling those which can't be
+/// carried over
+///
+/// Returns true once the book is empty, and the market is at the current version.
+pub fn migrate_orders(env: &Env, max_orders: u32) -> Result<bool, DexMarketError> {
+    let version = schema_version(env);
+
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(DexMarketError::UnsupportedSchemaVersion);
+    }
+
+    if version == CURRENT_SCHEMA_VERSION {
+        return Ok(true);
+    }
+
+    if !env.storage().instance().has(&MIGRATING_ORDERS) {
+        return Err(DexMarketError::MigrationRequired);
+    }
+
+    let market_info: DexMarketInfo = env
+        .storage()
+        .instance()
+        .get(&MARKET_INFO)
+        .ok_or(DexMarketError::NotInitialized)?;
+
+    let legacy_book = OrderBook::<LegacyOrderDetail>::open(env, LEGACY_ORDER_BOOK_PREFIX);
+    let order_book = crate::order_book_state(env);
+
+    // orders are taken from the front of the book, so each one is only read once
+    let first_order = || {
+        [OrderSide::Bid, OrderSide::Ask]
+            .into_iter()
+            .find_map(|side| legacy_book.orders(side).into_iter().next())
+    };
+
+    for _ in 0..max_orders {
+        let Some(id) = first_order() else {
+            break;
+        };
+
+        let entry = legacy_book.get_order(&id);
+
+        legacy_book
+            .cancel_order(&id)
+            .map_err(|_| DexMarketError::InvalidOrderId)?;
+
+        if let Some(entry) = entry {
+            move_order(env, &market_info, &order_book, entry)?;
+        }
+    }
+
+    if first_order().is_some() {
+        return Ok(false);
+    }
+
+    env.storage().instance().remove(&MIGRATING_ORDERS);
+    set_schema_version(env, CURRENT_SCHEMA_VERSION);
+    events::schema_migrated(env, CURRENT_SCHEMA_VERSION);
+
+    Ok(true)
+}
+
+/// Add the decimals of the tokens to the market info, reading them from the tokens like `init`,
+/// and set up the configuration version 0 didn't have
+fn migrate_market_info(env: &Env, admin: &Address) -> Result<(), DexMarketError> {
+    let legacy: LegacyDexMarketInfo = env
+        .storage()
+        .instance()
+        .get(&MARKET_INFO)
+        .ok_or(DexMarketError::NotInitialized)?;
+
+    let info = DexMarketInfo {
+        base_decimals: token::Client::new(env, &legacy.base_token).decimals(),
+        quote_decimals: token::Client::new(env, &legacy.quote_token).decimals(),
+        base_token: legacy.base_token,
+        quote_token: legacy.quote_token,
+        base_min_order_size: legacy.base_min_order_size,
+        price_tick: 1,
+        fee_authority: admin.clone(),
+        maker_fee_bps: 0,
+        taker_fee_bps: 0,
+    };
+
+    price::validate_decimals(info.base_decimals, info.quote_decimals)?;
+
+    crate::admin::set_admin(env, admin);
+    crate::status::set_market_status(env, MarketStatus::Active);
+    crate::set_market_info(env, &info);
+
+    Ok(())
+}
+
+/// Post an order taken out of the version 0 book to the current book, or refund it if it can't
+/// be carried over exactly
+fn move_order(
+    env: &Env,
+    market_info: &DexMarketInfo,
+    order_book: &OrderBook<OrderDetail>,
+    entry: OrderEntry<OrderId, LegacyOrderDetail>,
+) -> Result<(), DexMarketError> {
+    let side = entry.id.side();
+    let owner = entry.details.owner;
+
+    // what version 0 held for the order
+    let (asset, escrow) = match side {
+        OrderSide::Ask => (Asset::Base, price::to_amount(entry.size)?),
+        OrderSide::Bid => (
+            Asset::Quote,
+            price::legacy_quote_amount(entry.price, entry.size)?,
+        ),
+    };
+
+    let price = price::rescale_legacy_price(market_info, entry.price).filter(|&price| {
+        side == OrderSide::Ask || price::bid_escrow(market_info, price, entry.size) == Ok(escrow)
+    });
+
+    match price {
+        Some(price) if entry.size > 0 => {
+            let details = OrderDetail {
+                owner: owner.clone(),
+                funding: Funding::Wallet,
+            };
+
+            let new_id = order_book
+                .post_order(side, price, entry.size, &details)
+                .map_err(|_| DexMarketError::InvalidPrice)?;
+
+            crate::owner_orders::add(env, &owner, &new_id);
+            events::order_migrated(env, &owner, &entry.id, &new_id);
+        }
+
+        _ => {
+            events::order_cancelled(
+                env,
+                &owner,
+                OrderCancelledEvent {
+                    order_id: entry.id,
+                    unfilled_size: entry.size,
+                },
+            );
+
+            Funds::new(env, market_info).settle(&owner, Funding::Wallet, asset, escrow)?;
+        }
+    }
+
+    Ok(())
+}
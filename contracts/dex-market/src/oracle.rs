@@ -0,0 +1,212 @@
+//! A time weighted average price of the trades in the market
+//!
+//! The oracle keeps a running sum of the traded price multiplied by the time it was in effect.
+//! Whenever a trade is made after a new period of `resolution` seconds started, the sum at the
+//! start of that period is recorded. Since the price only changes with trades, the sum at any
+//! period boundary can be recovered from the next recorded observation, which makes the average
+//! price over any whole number of periods available, as long as it's still in the history.
+
+use soroban_sdk::{contracttype, symbol_short, vec, Env, Symbol, Vec};
+
+use crate::DexMarketError;
+
+/// Storage key for the running price accumulator
+const ACCUMULATOR: Symbol = symbol_short!("TWAPACC");
+
+/// Storage key for the accumulator values recorded at period boundaries
+const OBSERVATIONS: Symbol = symbol_short!("TWAPOBS");
+
+/// Storage key for the length of the periods prices are averaged over
+const RESOLUTION: Symbol = symbol_short!("TWAPRES");
+
+/// The number of decimals in the prices reported by the oracle
+pub const DECIMALS: u32 = 14;
+
+/// The number of observations kept in the history
+pub const MAX_OBSERVATIONS: u32 = 128;
+
+/// The period length used until the admin configures one, in seconds
+const DEFAULT_RESOLUTION: u32 = 300;
+
+/// A price reported by the oracle, following SEP-40
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PriceData {
+    /// The average price over the period ending at `timestamp`, with [DECIMALS] decimals
+    pub price: i128,
+
+    /// The ledger timestamp at the end of the period
+    pub timestamp: u64,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Accumulator {
+    /// The earliest time the accumulated value is known for
+    start: u64,
+
+    /// The sum of the traded price (U32F32 format) multiplied by the seconds it was in effect
+    cumulative: u128,
+
+    /// The price of the last trade (U32F32 format)
+    price: u64,
+
+    /// The ledger timestamp of the last trade
+    timestamp: u64,
+}
+
+impl Accumulator {
+    fn at(&self, timestamp: u64) -> u128 {
+        self.cumulative + self.price as u128 * (timestamp - self.timestamp) as u128
+    }
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Observation {
+    timestamp: u64,
+    cumulative: u128,
+
+    /// The price in effect at `timestamp`, which is also the price since the previous period
+    price: u64,
+}
+
+pub fn resolution(env: &Env) -> u32 {
+    env.storage()
+        .instance()
+        .get(&RESOLUTION)
+        .unwrap_or(DEFAULT_RESOLUTION)
+}
+
+/// Change the length of the periods prices are averaged over
+///
+/// The recorded history is discarded, since it was recorded at the previous resolution.
+pub fn set_resolution(env: &Env, resolution: u32) -> Result<(), DexMarketError> {
+    if resolution == 0 {
+        return Err(DexMarketError::InvalidResolution);
+    }
+
+    if let Some(mut accumulator) = accumulator(env) {
+        accumulator.start = accumulator.timestamp;
+        env.storage().instance().set(&ACCUMULATOR, &accumulator);
+    }
+
+    env.storage().persistent().remove(&OBSERVATIONS);
+    env.storage().instance().set(&RESOLUTION, &resolution);
+
+    Ok(())
+}
+
+fn accumulator(env: &Env) -> Option<Accumulator> {
+    env.storage().instance().get(&ACCUMULATOR)
+}
+
+fn observations(env: &Env) -> Vec<Observation> {
+    env.storage()
+        .persistent()
+        .get(&OBSERVATIONS)
+        .unwrap_or_else(|| vec![env])
+}
+
+/// Record a trade at the current ledger time
+pub fn record(env: &Env, price: u64) {
+    let now = env.ledger().timestamp();
+    let resolution = resolution(env) as u64;
+
+    let Some(mut accumulator) = accumulator(env) else {
+        let accumulator = Accumulator {
+            start: now,
+            cumulative: 0,
+            price,
+            timestamp: now,
+        };
+
+        env.storage().instance().set(&ACCUMULATOR, &accumulator);
+        return;
+    };
+
+    let period_start = now - now % resolution;
+    let mut observations = observations(env);
+    let is_new_period = match observations.last() {
+        Some(last) => last.timestamp < period_start,
+        None => true,
+    };
+
+    if is_new_period && period_start >= accumulator.timestamp {
+        if observations.len() == MAX_OBSERVATIONS {
+            let oldest = observations.pop_front_unchecked();
+            accumulator.start = accumulator.start.max(oldest.timestamp + resolution);
+        }
+
+        observations.push_back(Observation {
+            timestamp: period_start,
+            cumulative: accumulator.at(period_start),
+            price: accumulator.price,
+        });
+
+        env.storage().persistent().set(&OBSERVATIONS, &observations);
+    }
+
+    accumulator.cumulative = accumulator.at(now);
+    accumulator.price = price;
+    accumulator.timestamp = now;
+
+    env.storage().instance().set(&ACCUMULATOR, &accumulator);
+}
+
+/// The accumulated value at a period boundary, if it is still known
+fn cumulative_at(
+    accumulator: &Accumulator,
+    observations: &Vec<Observation>,
+    timestamp: u64,
+) -> Option<u128> {
+    if timestamp < accumulator.start {
+        return None;
+    }
+
+    // no trades were made between a boundary and the next observation after it
+    for observation in observations.iter() {
+        if observation.timestamp >= timestamp {
+            let elapsed = (observation.timestamp - timestamp) as u128;
+            return Some(observation.cumulative - observation.price as u128 * elapsed);
+        }
+    }
+
+    match timestamp >= accumulator.timestamp {
+        true => Some(accumulator.at(timestamp)),
+        false => None,
+    }
+}
+
+/// The average price over the `periods` periods which end at a boundary
+///
+/// Returns `None` if the market has no trades for the whole window, or if the window is older
+/// than the recorded history.
+pub fn average_price(env: &Env, end: u64, periods: u32) -> Option<PriceData> {
+    let resolution = resolution(env) as u64;
+    let end = end - end % resolution;
+
+    if periods == 0 || end > env.ledger().timestamp() {
+        return None;
+    }
+
+    let window = resolution.checked_mul(periods as u64)?;
+    let start = end.checked_sub(window)?;
+
+    let accumulator = accumulator(env)?;
+    let observations = observations(env);
+
+    let end_cumulative = cumulative_at(&accumulator, &observations, end)?;
+    let start_cumulative = cumulative_at(&accumulator, &observations, start)?;
+    let average = (end_cumulative - start_cumulative) / window as u128;
+
+    Some(PriceData {
+        price: to_decimal(average),
+        timestamp: end,
+    })
+}
+
+/// Convert a U32F32 price into a decimal with [DECIMALS] decimals
+fn to_decimal(price: u128) -> i128 {
+    ((price * 10u128.pow(DECIMALS)) >> 32) as i128
+}
@@ -0,0 +1,172 @@
+//! The orders each owner has resting in the book
+//!
+//! Anyone can grow the book by placing orders, so finding an owner's orders by searching it has
+//! no bounded cost. Instead each owner's orders are kept in a list as they're posted, and
+//! removed as they're filled or cancelled.
+//!
+//! The list is linked through an entry per order, so adding or removing an order only touches
+//! that order's entry, its neighbours and the ends of the list, however many orders the owner
+//! has.
+
+use soroban_sdk::{contracttype, symbol_short, Address, Env, Symbol, Vec};
+
+use crate::OrderId;
+
+/// Storage key prefix for the ends of each owner's list, and for the entry of each order in it
+const OWNER_ORDERS: Symbol = symbol_short!("OWNORDERS");
+
+/// A link to an order in an owner's list
+#[contracttype]
+#[derive(Clone)]
+enum Link {
+    None,
+    Order(OrderId),
+}
+
+/// The oldest and newest of an owner's orders
+#[contracttype]
+#[derive(Clone)]
+struct OwnerOrders {
+    first: Link,
+    last: Link,
+}
+
+/// The orders placed before and after an order by the same owner
+#[contracttype]
+#[derive(Clone)]
+struct OrderLinks {
+    prev: Link,
+    next: Link,
+}
+
+fn ends(env: &Env, owner: &Address) -> OwnerOrders {
+    env.storage()
+        .persistent()
+        .get(&(OWNER_ORDERS, owner.clone()))
+        .unwrap_or(OwnerOrders {
+            first: Link::None,
+            last: Link::None,
+        })
+}
+
+fn set_ends(env: &Env, owner: &Address, ends: &OwnerOrders) {
+    let key = (OWNER_ORDERS, owner.clone());
+
+    match ends.first {
+        Link::None => env.storage().persistent().remove(&key),
+        Link::Order(_) => env.storage().persistent().set(&key, ends),
+    }
+}
+
+fn links(env: &Env, owner: &Address, order: &OrderId) -> Option<OrderLinks> {
+    env.storage()
+        .persistent()
+        .get(&(OWNER_ORDERS, owner.clone(), order.clone()))
+}
+
+fn set_links(env: &Env, owner: &Address, order: &OrderId, links: &OrderLinks) {
+    env.storage()
+        .persistent()
+        .set(&(OWNER_ORDERS, owner.clone(), order.clone()), links);
+}
+
+fn update_links(env: &Env, owner: &Address, order: &OrderId, update: impl FnOnce(&mut OrderLinks)) {
+    if let Some(mut links) = links(env, owner, order) {
+        update(&mut links);
+        set_links(env, owner, order, &links);
+    }
+}
+
+/// The oldest of an owner's resting orders
+pub fn first(env: &Env, owner: &Address) -> Option<OrderId> {
+    match ends(env, owner).first {
+        Link::None => None,
+        Link::Order(order) => Some(order),
+    }
+}
+
+/// Up to `max_orders` of an owner's resting orders, oldest first, starting after `after`, or
+/// with the oldest if it's `None`
+///
+/// Nothing is returned if `after` isn't one of the owner's orders.
+pub fn orders(env: &Env, owner: &Address, after: Option<OrderId>, max_orders: u32) -> Vec<OrderId> {
+    let mut next = match after {
+        None => ends(env, owner).first,
+        Some(after) => links(env, owner, &after).map_or(Link::None, |links| links.next),
+    };
+
+    let mut orders = Vec::new(env);
+
+    while orders.len() < max_orders {
+        let Link::Order(order) = next else {
+            break;
+        };
+
+        next = links(env, owner, &order).map_or(Link::None, |links| links.next);
+        orders.push_back(order);
+    }
+
+    orders
+}
+
+/// Record an order posted to the book
+pub fn add(env: &Env, owner: &Address, order: &OrderId) {
+    if links(env, owner, order).is_some() {
+        return;
+    }
+
+    let mut ends = ends(env, owner);
+    let link = Link::Order(order.clone());
+
+    match &ends.last {
+        Link::None => ends.first = link.clone(),
+        Link::Order(last) => update_links(env, owner, last, |links| links.next = link.clone()),
+    }
+
+    set_links(
+        env,
+        owner,
+        order,
+        &OrderLinks {
+            prev: ends.last,
+            next: Link::None,
+        },
+    );
+
+    ends.last = link;
+    set_ends(env, owner, &ends);
+}
+
+/// Forget an order which was filled or cancelled
+pub fn remove(env: &Env, owner: &Address, order: &OrderId) {
+    let Some(removed) = links(env, owner, order) else {
+        return;
+    };
+
+    env.storage()
+        .persistent()
+        .remove(&(OWNER_ORDERS, owner.clone(), order.clone()));
+
+    if let Link::Order(prev) = &removed.prev {
+        update_links(env, owner, prev, |links| links.next = removed.next.clone());
+    }
+
+    if let Link::Order(next) = &removed.next {
+        update_links(env, owner, next, |links| links.prev = removed.prev.clone());
+    }
+
+    // the ends of the list only change if the order was at one of them
+    if matches!(removed.prev, Link::None) || matches!(removed.next, Link::None) {
+        let mut ends = ends(env, owner);
+
+        if let Link::None = removed.prev {
+            ends.first = removed.next.clone();
+        }
+
+        if let Link::None = removed.next {
+            ends.last = removed.prev;
+        }
+
+        set_ends(env, owner, &ends);
+    }
+}
@@ -0,0 +1,172 @@
+//! Conversions between prices and token amounts
+//!
+//! Prices are the number of whole quote tokens paid for one whole base token, in U32F32 format,
+//! so they read the same whatever the decimals of the tokens are. Token amounts are always in
+//! the smallest units of each token, so converting between them is scaled by the difference in
+//! the decimals of the tokens, which is captured when the market is initialized.
+//!
+//! Rounding always favours the market. Quote tokens paid into the market are rounded up, and
+//! quote tokens paid out of it are rounded down, with the difference kept as dust.
+//!
+//! - The escrow for a bid is its quote value rounded up. When a resting bid is filled, the
+//!   escrow it uses is the difference between its escrow before and after the fill, so the
+//!   escrow used by all of its fills and the refund of what's left add up to exactly the
+//!   escrow it started with.
+//! - A taker bid pays the exact value of all of its fills, rounded up once.
+//! - The party receiving quote tokens for a fill gets the value of that fill rounded down.
+//!
+//! Every conversion is checked, and fails with [DexMarketError::ArithmeticOverflow] when an
+//! intermediate value doesn't fit, or [DexMarketError::AmountTooLarge] when the resulting token
+//! amount doesn't.
+
+use crate::{DexMarketError, DexMarketInfo};
+
+/// The largest difference between the decimals of the tokens in a market
+pub const MAX_DECIMALS_DIFFERENCE: u32 = 18;
+
+/// The number of fractional bits in a price
+const PRICE_FRACTIONAL_BITS: u32 = 32;
+
+/// Raw quote amounts are `price * base_amount * numerator / denominator`
+struct Scale {
+    numerator: u128,
+    denominator: u128,
+}
+
+impl Scale {
+    fn new(market_info: &DexMarketInfo) -> Self {
+        let base_decimals = market_info.base_decimals;
+        let quote_decimals = market_info.quote_decimals;
+
+        let (numerator, denominator) = match quote_decimals >= base_decimals {
+            true => (10u128.pow(quote_decimals - base_decimals), 1),
+            false => (1, 10u128.pow(base_decimals - quote_decimals)),
+        };
+
+        Self {
+            numerator,
+            denominator: denominator << PRICE_FRACTIONAL_BITS,
+        }
+    }
+}
+
+/// The exact quote value of some base tokens, before it is rounded to whole quote units
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct QuoteValue(u128);
+
+impl QuoteValue {
+    pub fn new(price: u64, base_amount: u128) -> Result<Self, DexMarketError> {
+        (price as u128)
+            .checked_mul(base_amount)
+            .map(Self)
+            .ok_or(DexMarketError::ArithmeticOverflow)
+    }
+
+    pub fn add(self, other: Self) -> Result<Self, DexMarketError> {
+        self.0
+            .checked_add(other.0)
+            .map(Self)
+            .ok_or(DexMarketError::ArithmeticOverflow)
+    }
+
+    /// The value in quote tokens, rounded down
+    pub fn round_down(self, market_info: &DexMarketInfo) -> Result<i128, DexMarketError> {
+        let scale = Scale::new(market_info);
+
+        to_amount(self.scaled(&scale)? / scale.denominator)
+    }
+
+    /// The value in quote tokens, rounded up
+    pub fn round_up(self, market_info: &DexMarketInfo) -> Result<i128, DexMarketError> {
+        let scale = Scale::new(market_info);
+
+        to_amount(self.scaled(&scale)?.div_ceil(scale.denominator))
+    }
+
+    fn scaled(self, scale: &Scale) -> Result<u128, DexMarketError> {
+        self.0
+            .checked_mul(scale.numerator)
+            .ok_or(DexMarketError::ArithmeticOverflow)
+    }
+}
+
+/// The quote tokens paid for some base tokens in a market from before prices were in whole
+/// tokens, rounded down like that version of the market did
+///
+/// Those prices were the number of the smallest units of the quote token paid for one of the
+/// smallest units of the base token.
+pub fn legacy_quote_amount(price: u64, base_amount: u128) -> Result<i128, DexMarketError> {
+    to_amount(QuoteValue::new(price, base_amount)?.0 >> PRICE_FRACTIONAL_BITS)
+}
+
+/// The price in whole tokens of a price from a market from before prices were in whole tokens
+///
+/// Returns `None` if the price can't be represented exactly, or doesn't fit in the book.
+pub fn rescale_legacy_price(market_info: &DexMarketInfo, price: u64) -> Option<u64> {
+    let base_decimals = market_info.base_decimals;
+    let quote_decimals = market_info.quote_decimals;
+    let price = price as u128;
+
+    let price = match base_decimals >= quote_decimals {
+        true => price.checked_mul(10u128.pow(base_decimals - quote_decimals))?,
+        false => {
+            let divisor = 10u128.pow(quote_decimals - base_decimals);
+            (price % divisor == 0).then_some(price / divisor)?
+        }
+    };
+
+    match price > 0 && price <= orderbook::MAX_PRICE as u128 {
+        true => Some(price as u64),
+        false => None,
+    }
+}
+
+/// Convert an unsigned amount of tokens to the signed amounts tokens are transferred in
+pub fn to_amount(amount: u128) -> Result<i128, DexMarketError> {
+    i128::try_from(amount).map_err(|_| DexMarketError::AmountTooLarge)
+}
+
+/// Check that amounts can be converted between tokens with these decimals
+pub fn validate_decimals(base_decimals: u32, quote_decimals: u32) -> Result<(), DexMarketError> {
+    match base_decimals.abs_diff(quote_decimals) <= MAX_DECIMALS_DIFFERENCE {
+        true => Ok(()),
+        false => Err(DexMarketError::InvalidDecimals),
+    }
+}
+
+/// The quote tokens held in escrow for a bid
+pub fn bid_escrow(
+    market_info: &DexMarketInfo,
+    price: u64,
+    size: u128,
+) -> Result<i128, DexMarketError> {
+    QuoteValue::new(price, size)?.round_up(market_info)
+}
+
+/// The largest amount of base tokens which can be added to `value` with the total still costing
+/// at most `quote`, when rounded up
+pub fn max_size_for_quote(
+    market_info: &DexMarketInfo,
+    value: QuoteValue,
+    price: u64,
+    quote: i128,
+) -> u128 {
+    let scale = Scale::new(market_info);
+
+    let Some(limit) = (quote as u128).checked_mul(scale.denominator) else {
+        return u128::MAX;
+    };
+
+    (limit / scale.numerator).saturating_sub(value.0) / price as u128
+}
+
+/// The smallest amount of base tokens which pays out at least `quote` at a price
+pub fn min_size_for_quote(market_info: &DexMarketInfo, price: u64, quote: i128) -> u128 {
+    let scale = Scale::new(market_info);
+
+    (quote as u128)
+        .checked_mul(scale.denominator)
+        .zip((price as u128).checked_mul(scale.numerator))
+        .map(|(limit, value)| limit.div_ceil(value))
+        .unwrap_or(u128::MAX)
+}
@@ -0,0 +1,124 @@
+//! Fees shared with the referrers of orders
+//!
+//! An order can name a referrer, such as the frontend it was placed through. The referrer earns
+//! a share of the taker fee the order pays, after any maker rebates, so the market never pays
+//! out more than it collects. Earnings are held by the market until the referrer claims them.
+
+use soroban_sdk::{contracttype, symbol_short, Address, Env, Symbol};
+
+use crate::{funds::Asset, DexMarketError};
+
+/// The number of basis points in a whole
+const BPS_SCALE: i128 = 10_000;
+
+/// Storage key for the share of the taker fee paid to referrers, in basis points
+const REFERRAL_SHARE: Symbol = symbol_short!("REFSHARE");
+
+/// Storage key prefix for the earnings each referrer has not claimed yet
+const REFERRAL: Symbol = symbol_short!("REFERRAL");
+
+/// The referrer of an order
+///
+/// Contract types can't hold an `Option` here, so an order without a referrer is
+/// [Referrer::None].
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Referrer {
+    /// The order wasn't referred
+    None,
+
+    /// The order was referred by an address
+    Address(Address),
+}
+
+impl Referrer {
+    pub fn into_address(self) -> Option<Address> {
+        match self {
+            Referrer::None => None,
+            Referrer::Address(address) => Some(address),
+        }
+    }
+}
+
+/// Fees earned by a referrer which have not been claimed yet
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReferralEarnings {
+    /// The amount of base tokens earned
+    pub base: i128,
+
+    /// The amount of quote tokens earned
+    pub quote: i128,
+}
+
+pub fn referral_share(env: &Env) -> u32 {
+    env.storage().instance().get(&REFERRAL_SHARE).unwrap_or(0)
+}
+
+pub fn set_referral_share(env: &Env, share_bps: u32) -> Result<(), DexMarketError> {
+    if share_bps as i128 > BPS_SCALE {
+        return Err(DexMarketError::InvalidFeeSchedule);
+    }
+
+    env.storage().instance().set(&REFERRAL_SHARE, &share_bps);
+    crate::events::referral_share_changed(env, share_bps);
+
+    Ok(())
+}
+
+/// The referrer's share of the taker fee kept by the market, rounded down in favour of the
+/// market
+pub fn referral_fee(env: &Env, net_taker_fee: i128) -> Result<i128, DexMarketError> {
+    net_taker_fee
+        .checked_mul(referral_share(env) as i128)
+        .map(|amount| amount / BPS_SCALE)
+        .ok_or(DexMarketError::ArithmeticOverflow)
+}
+
+pub fn earnings(env: &Env, referrer: &Address) -> ReferralEarnings {
+    env.storage()
+        .persistent()
+        .get(&(REFERRAL, referrer.clone()))
+        .unwrap_or_default()
+}
+
+fn set_earnings(env: &Env, referrer: &Address, earnings: &ReferralEarnings) {
+    let key = (REFERRAL, referrer.clone());
+
+    match *earnings == ReferralEarnings::default() {
+        true => env.storage().persistent().remove(&key),
+        false => env.storage().persistent().set(&key, earnings),
+    }
+}
+
+/// Add fees to a referrer's earnings
+pub fn credit(
+    env: &Env,
+    referrer: &Address,
+    asset: Asset,
+    amount: i128,
+) -> Result<(), DexMarketError> {
+    if amount == 0 {
+        return Ok(());
+    }
+
+    let mut earnings = earnings(env, referrer);
+    let earned = match asset {
+        Asset::Base => &mut earnings.base,
+        Asset::Quote => &mut earnings.quote,
+    };
+
+    *earned = crate::add_amounts(*earned, amount)?;
+    set_earnings(env, referrer, &earnings);
+
+    Ok(())
+}
+
+/// Remove all of a referrer's earnings from the market's accounting, returning the amounts
+/// removed
+pub fn take_earnings(env: &Env, referrer: &Address) -> ReferralEarnings {
+    let earnings = earnings(env, referrer);
+    set_earnings(env, referrer, &ReferralEarnings::default());
+
+    earnings
+}
@@ -0,0 +1,290 @@
+//! Rewards for makers providing liquidity close to the mid price
+//!
+//! While orders can be placed, the market emits a reward token at a fixed rate per second, and
+//! shares it between the owners of the orders at the best price levels on each side of the book.
+//! Each order is weighted by its size and by how close its price is to the mid price, and orders
+//! further from the mid price than the band don't earn anything. Orders smaller than the minimum
+//! size of the configuration don't earn anything either.
+//!
+//! The book only changes through the market, so rewards are accrued just before every change to
+//! it, for the book as it stood since the last change. That makes the rewards exactly time
+//! weighted, without keeping track of each order. Since an order's weight depends on the mid
+//! price, which moves with every trade, the weights are summed from the book each time rather
+//! than kept as they change. Rewards are paid out of tokens funded into the market, and stop being
+//! emitted once those run out.
+
+use soroban_sdk::{contracttype, symbol_short, token, Address, Env, Map, Symbol};
+
+use crate::{DexMarketError, OrderBook, OrderDetail};
+
+/// The number of basis points in a whole
+const BPS_SCALE: u128 = 10_000;
+
+/// The number of orders read on each side of the book to weigh its liquidity, counted from the
+/// best price whether they earn rewards or not, so that accruing rewards on every change to the
+/// book reads a bounded part of it
+pub const MAX_WEIGHED_ORDERS: u32 = 20;
+
+/// Storage key for the reward configuration
+const REWARD_CONFIG: Symbol = symbol_short!("RWDCONFIG");
+
+/// Storage key for the funded reward tokens which have not been emitted yet
+const REWARD_POOL: Symbol = symbol_short!("RWDPOOL");
+
+/// Storage key for the ledger timestamp rewards were last accrued at
+const REWARD_TIME: Symbol = symbol_short!("RWDTIME");
+
+/// Storage key prefix for the rewards each owner has earned but not claimed yet
+const REWARDS: Symbol = symbol_short!("REWARDS");
+
+/// How liquidity in the market is rewarded
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RewardConfig {
+    /// The token rewards are paid in
+    pub token: Address,
+
+    /// The amount of reward tokens emitted per second
+    pub emission_rate: i128,
+
+    /// How far from the mid price orders can be priced to earn rewards, in basis points of the
+    /// mid price
+    pub band_bps: u32,
+
+    /// The smallest remaining size, in base tokens, an order needs to earn rewards
+    pub min_order_size: u128,
+}
+
+pub fn config(env: &Env) -> Option<RewardConfig> {
+    env.storage().instance().get(&REWARD_CONFIG)
+}
+
+/// Change how liquidity is rewarded from now on
+///
+/// The reward token can't be changed once it's set, since unclaimed rewards are owed in it.
+pub fn set_config(env: &Env, config: &RewardConfig) -> Result<(), DexMarketError> {
+    let is_valid = config.emission_rate >= 0
+        && config.min_order_size > 0
+        && config.band_bps > 0
+        && config.band_bps as u128 <= BPS_SCALE
+        && self::config(env).map_or(true, |current| current.token == config.token);
+
+    if !is_valid {
+        return Err(DexMarketError::InvalidRewardConfig);
+    }
+
+    // the time until now is rewarded with the previous configuration
+    accrue(env)?;
+
+    env.storage().instance().set(&REWARD_CONFIG, config);
+    env.storage()
+        .instance()
+        .set(&REWARD_TIME, &env.ledger().timestamp());
+
+    crate::events::reward_config_changed(env, config);
+
+    Ok(())
+}
+
+/// The funded reward tokens which have not been emitted yet
+pub fn pool(env: &Env) -> i128 {
+    env.storage().instance().get(&REWARD_POOL).unwrap_or(0)
+}
+
+fn set_pool(env: &Env, amount: i128) {
+    env.storage().instance().set(&REWARD_POOL, &amount);
+}
+
+/// Transfer reward tokens into the market, to be emitted as rewards
+pub fn fund(env: &Env, from: &Address, amount: i128) -> Result<(), DexMarketError> {
+    let config = config(env).ok_or(DexMarketError::RewardsNotConfigured)?;
+
+    if amount <= 0 {
+        return Err(DexMarketError::InvalidAmount);
+    }
+
+    // the new tokens are only emitted from now on
+    accrue(env)?;
+
+    let pool = pool(env)
+        .checked_add(amount)
+        .ok_or(DexMarketError::AmountTooLarge)?;
+
+    token::Client::new(env, &config.token).transfer(from, &env.current_contract_address(), &amount);
+    set_pool(env, pool);
+
+    Ok(())
+}
+
+/// The rewards an owner has earned but not claimed, up to the last change to the book
+pub fn earned(env: &Env, owner: &Address) -> i128 {
+    env.storage()
+        .persistent()
+        .get(&(REWARDS, owner.clone()))
+        .unwrap_or(0)
+}
+
+fn set_earned(env: &Env, owner: &Address, amount: i128) {
+    let key = (REWARDS, owner.clone());
+
+    match amount {
+        0 => env.storage().persistent().remove(&key),
+        _ => env.storage().persistent().set(&key, &amount),
+    }
+}
+
+/// Transfer all the rewards an owner has earned to them, returning the amount transferred
+pub fn claim(env: &Env, owner: &Address) -> Result<i128, DexMarketError> {
+    let config = config(env).ok_or(DexMarketError::RewardsNotConfigured)?;
+
+    accrue(env)?;
+
+    let amount = earned(env, owner);
+    set_earned(env, owner, 0);
+
+    if amount > 0 {
+        token::Client::new(env, &config.token).transfer(
+            &env.current_contract_address(),
+            owner,
+            &amount,
+        );
+    }
+
+    Ok(amount)
+}
+
+/// Share the rewards emitted since they were last accrued between the owners of the orders in
+/// the book
+///
+/// This has to be called before every change to the book or to the market status. Nothing is
+/// emitted for the time orders couldn't be placed, or the book had no mid price because a side
+/// was empty, and the rewards for that time stay in the pool.
+pub fn accrue(env: &Env) -> Result<(), DexMarketError> {
+    let Some(config) = config(env) else {
+        return Ok(());
+    };
+
+    let now = env.ledger().timestamp();
+    let last = env.storage().instance().get(&REWARD_TIME).unwrap_or(now);
+
+    if now <= last {
+        return Ok(());
+    }
+
+    env.storage().instance().set(&REWARD_TIME, &now);
+
+    // nothing is emitted while the book can't be traded against
+    if crate::status::market_status(env)
+        .check_place_order()
+        .is_err()
+    {
+        return Ok(());
+    }
+
+    let pool = pool(env);
+    let emission = config
+        .emission_rate
+        .saturating_mul((now - last) as i128)
+        .min(pool);
+
+    if emission <= 0 {
+        return Ok(());
+    }
+
+    let order_book = crate::order_book_state(env);
+
+    let Some(mid_price) = crate::mid_price(&order_book) else {
+        return Ok(());
+    };
+
+    let weights = liquidity_weights(env, &order_book, mid_price, &config);
+    let total_weight = weights.values().iter().sum::<u128>();
+
+    if total_weight == 0 {
+        return Ok(());
+    }
+
+    let mut emitted = 0i128;
+
+    for (owner, weight) in weights {
+        let reward = share(emission, weight, total_weight);
+
+        let earned = earned(env, &owner)
+            .checked_add(reward)
+            .ok_or(DexMarketError::AmountTooLarge)?;
+
+        set_earned(env, &owner, earned);
+        emitted = emitted
+            .checked_add(reward)
+            .ok_or(DexMarketError::AmountTooLarge)?;
+    }
+
+    let pool = pool
+        .checked_sub(emitted)
+        .ok_or(DexMarketError::AmountTooLarge)?;
+
+    set_pool(env, pool);
+
+    Ok(())
+}
+
+/// The weight of the liquidity each owner provides within the band around the mid price
+///
+/// An order's weight is its size, scaled by how much closer to the mid price it is than the edge
+/// of the band. Only the first [MAX_WEIGHED_ORDERS] orders on each side are read, including the
+/// ones too small to earn anything.
+fn liquidity_weights(
+    env: &Env,
+    order_book: &OrderBook<OrderDetail>,
+    mid_price: u64,
+    config: &RewardConfig,
+) -> Map<Address, u128> {
+    use orderbook::OrderSide;
+
+    let band_bps = config.band_bps as u128;
+    let mut weights = Map::new(env);
+
+    for side in [OrderSide::Bid, OrderSide::Ask] {
+        for id in order_book
+            .orders(side)
+            .into_iter()
+            .take(MAX_WEIGHED_ORDERS as usize)
+        {
+            let Some(order) = order_book.get_order(&id) else {
+                continue;
+            };
+
+            // orders are sorted by price, so the rest of the side is further away
+            let distance_bps =
+                order.price.abs_diff(mid_price) as u128 * BPS_SCALE / mid_price as u128;
+
+            if distance_bps >= band_bps {
+                break;
+            }
+
+            if order.size < config.min_order_size {
+                continue;
+            }
+
+            let owner = order.details.owner;
+            let weight = order.size * (band_bps - distance_bps);
+
+            weights.set(owner.clone(), weights.get(owner).unwrap_or(0) + weight);
+        }
+    }
+
+    weights
+}
+
+/// The part of `amount` for `weight` out of `total_weight`, rounded down
+fn share(amount: i128, weight: u128, total_weight: u128) -> i128 {
+    let amount = amount as u128;
+
+    // only a huge emission can overflow, and is then rounded down a little further
+    let share = amount
+        .checked_mul(weight)
+        .map(|amount| amount / total_weight)
+        .unwrap_or(amount / total_weight * weight);
+
+    share as i128
+}
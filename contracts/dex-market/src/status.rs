@@ -0,0 +1,72 @@
+use soroban_sdk::{contracttype, symbol_short, Env, Symbol};
+
+use crate::DexMarketError;
+
+/// Storage key for the trading status of the market
+const STATUS: Symbol = symbol_short!("STATUS");
+
+/// The trading state of a market
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum MarketStatus {
+    /// Orders can be placed and cancelled normally
+    Active = 0,
+
+    /// Orders can only be placed if they don't match any order in the book
+    PostOnly = 1,
+
+    /// No new orders can be placed, but existing orders can be cancelled
+    CancelOnly = 2,
+
+    /// No orders can be placed or cancelled
+    Halted = 3,
+
+    /// The market is winding down, and resting orders are being refunded
+    Closing = 4,
+
+    /// The market is permanently closed
+    Closed = 5,
+}
+
+impl MarketStatus {
+    /// Check that orders can be placed in the market
+    pub fn check_place_order(&self) -> Result<(), DexMarketError> {
+        match self {
+            MarketStatus::Active | MarketStatus::PostOnly => Ok(()),
+            MarketStatus::CancelOnly => Err(DexMarketError::MarketCancelOnly),
+            MarketStatus::Halted => Err(DexMarketError::MarketHalted),
+            MarketStatus::Closing => Err(DexMarketError::MarketClosing),
+            MarketStatus::Closed => Err(DexMarketError::MarketClosed),
+        }
+    }
+
+    /// Check that the market has not started closing
+    pub fn check_open(&self) -> Result<(), DexMarketError> {
+        match self {
+            MarketStatus::Closing => Err(DexMarketError::MarketClosing),
+            MarketStatus::Closed => Err(DexMarketError::MarketClosed),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that orders can be cancelled in the market
+    pub fn check_cancel_order(&self) -> Result<(), DexMarketError> {
+        match self {
+            MarketStatus::Halted => Err(DexMarketError::MarketHalted),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn market_status(env: &Env) -> MarketStatus {
+    env.storage()
+        .instance()
+        .get(&STATUS)
+        .unwrap_or(MarketStatus::Active)
+}
+
+pub fn set_market_status(env: &Env, status: MarketStatus) {
+    env.storage().instance().set(&STATUS, &status);
+    crate::events::status_changed(env, status);
+}
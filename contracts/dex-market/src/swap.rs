@@ -0,0 +1,128 @@
+use orderbook::{OrderBook, OrderSide};
+
+use crate::{
+    fees::FillFees,
+    price::{max_size_for_quote, min_size_for_quote, to_amount, QuoteValue},
+    DexMarketError, DexMarketInfo, OrderDetail,
+};
+
+/// The number of basis points in a whole
+const BPS_SCALE: i128 = 10_000;
+
+/// An order that fills against the book immediately, limited by the amounts it can pay and
+/// receive rather than by its size
+pub struct FillPlan {
+    /// The size of the order which fills within the limits (in base tokens)
+    pub size: u128,
+
+    /// The amount the taker pays for the fills
+    pub amount_in: i128,
+
+    /// The amount the taker receives from the fills, after fees
+    pub amount_out: i128,
+}
+
+impl FillPlan {
+    /// Find how much of the book an order can take
+    ///
+    /// # Params
+    ///
+    /// `side` - The side of the order taking liquidity
+    /// `max_in` - The most the taker is willing to pay, in the token they pay with
+    /// `max_out` - The amount the taker wants to receive after fees, in the token they receive
+    pub fn new(
+        order_book: &OrderBook<OrderDetail>,
+        market_info: &DexMarketInfo,
+        side: OrderSide,
+        max_in: i128,
+        max_out: i128,
+    ) -> Result<Self, DexMarketError> {
+        let mut taker_value = QuoteValue::default();
+        let mut plan = Self {
+            size: 0,
+            amount_in: 0,
+            amount_out: 0,
+        };
+
+        for id in order_book.orders(side.opposite()) {
+            let Some(order) = order_book.get_order(&id) else {
+                continue;
+            };
+
+            // the amount still needed before fees, which are taken from each fill
+            let out_needed = gross_amount(max_out - plan.amount_out, market_info.taker_fee_bps);
+
+            let size = order.size.min(match side {
+                OrderSide::Bid => (out_needed as u128).min(max_size_for_quote(
+                    market_info,
+                    taker_value,
+                    order.price,
+                    max_in,
+                )),
+
+                OrderSide::Ask => ((max_in - plan.amount_in) as u128).min(min_size_for_quote(
+                    market_info,
+                    order.price,
+                    out_needed,
+                )),
+            });
+
+            if size == 0 {
+                break;
+            }
+
+            let fill_value = QuoteValue::new(order.price, size)?;
+            let base_amount = to_amount(size)?;
+            let quote_amount = fill_value.round_down(market_info)?;
+
+            // a bid pays for all of its fills at once, rounded up
+            let (amount_in, maker_amount, taker_amount) = match side {
+                OrderSide::Bid => {
+                    taker_value = taker_value.add(fill_value)?;
+
+                    (
+                        taker_value.round_up(market_info)?,
+                        quote_amount,
+                        base_amount,
+                    )
+                }
+
+                // the size is limited to what's left of `max_in`, so this can't overflow
+                OrderSide::Ask => (plan.amount_in + base_amount, base_amount, quote_amount),
+            };
+
+            let taker_fee = FillFees::new(market_info, maker_amount, taker_amount)?.taker_fee;
+
+            plan.size = plan
+                .size
+                .checked_add(size)
+                .ok_or(DexMarketError::ArithmeticOverflow)?;
+            plan.amount_in = amount_in;
+            plan.amount_out = plan
+                .amount_out
+                .checked_add(taker_amount - taker_fee)
+                .ok_or(DexMarketError::ArithmeticOverflow)?;
+
+            if size < order.size || plan.amount_in >= max_in || plan.amount_out >= max_out {
+                break;
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+/// The smallest amount that is at least `net_amount` after the taker fee is deducted
+fn gross_amount(net_amount: i128, taker_fee_bps: u32) -> i128 {
+    let net_bps = BPS_SCALE - taker_fee_bps as i128;
+
+    if net_bps <= 0 {
+        return i128::MAX;
+    }
+
+    // fees are rounded up, so what's left after them is rounded down
+    net_amount
+        .checked_mul(BPS_SCALE)
+        .map(|amount| (amount + net_bps - 1) / net_bps)
+        .unwrap_or(i128::MAX)
+}
@@ -0,0 +1,421 @@
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt::Debug;
+
+use dex_market_client::{Client as DexMarketClient, DexMarketError, OrderSide};
+use soroban_sdk::{
+    contract, contracterror, contractimpl, panic_with_error, symbol_short, vec, Address, Env,
+    Error, Vec,
+};
+
+/// The most markets a swap can be routed through
+pub const MAX_HOPS: u32 = 4;
+
+pub trait DexRouter {
+    type Error;
+
+    fn swap_exact_in(
+        env: Env,
+        trader: Address,
+        token_in: Address,
+        path: Vec<Address>,
+        amount_in: i128,
+        min_out: i128,
+    ) -> Result<i128, Self::Error>;
+    fn swap_exact_out(
+        env: Env,
+        trader: Address,
+        token_in: Address,
+        path: Vec<Address>,
+        amount_out: i128,
+        max_in: i128,
+    ) -> Result<i128, Self::Error>;
+}
+
+#[contracterror]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum DexRouterError {
+    InvalidPath = 100,
+    InvalidAmount = 101,
+    SlippageExceeded = 102,
+    InsufficientLiquidity = 103,
+}
+
+#[contract]
+pub struct DexRouterContract;
+
+#[contractimpl]
+impl DexRouter for DexRouterContract {
+    type Error = DexRouterError;
+
+    /// Swap an exact amount of `token_in` through each market in `path`
+    ///
+    /// The output of each market is the input of the next one, and fills immediately without
+    /// leaving an order on the book. Each market takes as much of its input as its book can fill.
+    /// The trader trades with each market directly, so whatever a market doesn't take, of
+    /// `amount_in` or of a token in between, never leaves the trader's account, and they have to
+    /// authorize the token transfers each market makes. The swap fails with
+    /// `InsufficientLiquidity` if a market can't fill any of its input. Returns the amount
+    /// received from the last market, which has to be at least `min_out`.
+    fn swap_exact_in(
+        env: Env,
+        trader: Address,
+        token_in: Address,
+        path: Vec<Address>,
+        amount_in: i128,
+        min_out: i128,
+    ) -> Result<i128, DexRouterError> {
+        if amount_in <= 0 || min_out < 0 {
+            return Err(DexRouterError::InvalidAmount);
+        }
+
+        trader.require_auth();
+
+        let (sides, token_out) = route(&env, &token_in, &path)?;
+        let mut amount = amount_in;
+
+        for (market, side) in path.iter().zip(sides.iter()) {
+            let result =
+                DexMarketClient::new(&env, &market).try_swap_exact_in(&trader, &side, &amount, &0);
+
+            (_, amount) = hop_result(&env, result)?;
+        }
+
+        if amount < min_out {
+            return Err(DexRouterError::SlippageExceeded);
+        }
+
+        publish_swap(&env, trader, token_in, token_out, amount_in, amount);
+
+        Ok(amount)
+    }
+
+    /// Swap `token_in` through each market in `path` to receive an exact amount
+    ///
+    /// The amount each market has to deliver is worked out backwards from the end of the path
+    /// first, so that no more is bought at each step than the next one needs. Returns the amount
+    /// of `token_in` paid to the first market, which has to be at most `max_in`.
+    fn swap_exact_out(
+        env: Env,
+        trader: Address,
+        token_in: Address,
+        path: Vec<Address>,
+        amount_out: i128,
+        max_in: i128,
+    ) -> Result<i128, DexRouterError> {
+        if amount_out <= 0 || max_in < 0 {
+            return Err(DexRouterError::InvalidAmount);
+        }
+
+        trader.require_auth();
+
+        let (sides, token_out) = route(&env, &token_in, &path)?;
+
+        // amounts[i] is paid to the market at path[i], and the last amount is the output
+        let mut amounts = vec![&env, amount_out];
+
+        for (market, side) in path.iter().zip(sides.iter()).rev() {
+            let target = amounts.first_unchecked();
+            let quote = DexMarketClient::new(&env, &market).quote_fill(&side, &i128::MAX, &target);
+
+            if quote.amount_out < target {
+                return Err(DexRouterError::InsufficientLiquidity);
+            }
+
+            amounts.push_front(quote.amount_in);
+        }
+
+        let amount_in = amounts.first_unchecked();
+
+        if amount_in > max_in {
+            return Err(DexRouterError::SlippageExceeded);
+        }
+
+        for (index, (market, side)) in path.iter().zip(sides.iter()).enumerate() {
+            let index = index as u32;
+            let result = DexMarketClient::new(&env, &market).try_swap_exact_out(
+                &trader,
+                &side,
+                &amounts.get_unchecked(index + 1),
+                &amounts.get_unchecked(index),
+            );
+
+            hop_result(&env, result)?;
+        }
+
+        publish_swap(&env, trader, token_in, token_out, amount_in, amount_out);
+
+        Ok(amount_in)
+    }
+}
+
+/// The side of the order to place in each market of a path, and the token the path ends with
+///
+/// Each market has to trade the token the previous one delivers.
+fn route(
+    env: &Env,
+    token_in: &Address,
+    path: &Vec<Address>,
+) -> Result<(Vec<OrderSide>, Address), DexRouterError> {
+    if path.is_empty() || path.len() > MAX_HOPS {
+        return Err(DexRouterError::InvalidPath);
+    }
+
+    let mut sides = Vec::new(env);
+    let mut token = token_in.clone();
+
+    for market in path.iter() {
+        let info = DexMarketClient::new(env, &market).market_info();
+
+        if token == info.base_token {
+            sides.push_back(OrderSide::Ask);
+            token = info.quote_token;
+        } else if token == info.quote_token {
+            sides.push_back(OrderSide::Bid);
+            token = info.base_token;
+        } else {
+            return Err(DexRouterError::InvalidPath);
+        }
+    }
+
+    Ok((sides, token))
+}
+
+/// The result of a swap in one of the markets of a path
+///
+/// A market whose book can't fill the swap fails the route with `InsufficientLiquidity`, and any
+/// other error the market fails with is raised as it is.
+fn hop_result<T, E: Debug>(
+    env: &Env,
+    result: Result<Result<T, E>, Result<DexMarketError, Error>>,
+) -> Result<T, DexRouterError> {
+    match result {
+        Ok(value) => Ok(value.expect("the market returned a malformed result")),
+        Err(Ok(DexMarketError::InsufficientLiquidity)) => {
+            Err(DexRouterError::InsufficientLiquidity)
+        }
+        Err(Ok(error)) => panic_with_error!(env, error),
+        Err(Err(error)) => panic_with_error!(env, error),
+    }
+}
+
+fn publish_swap(
+    env: &Env,
+    trader: Address,
+    token_in: Address,
+    token_out: Address,
+    amount_in: i128,
+    amount_out: i128,
+) {
+    env.events().publish(
+        (symbol_short!("swap"), trader),
+        (token_in, token_out, amount_in, amount_out),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use dex_market::{
+        DexMarketContract, DexMarketContractClient, DexMarketInfo, Funding, OrderParams, Referrer,
+    };
+    use soroban_sdk::testutils::Address as _;
+
+    use super::*;
+
+    struct TestEnv {
+        env: Env,
+        tokens: std::vec::Vec<Address>,
+        markets: std::vec::Vec<Address>,
+        trader: Address,
+        router: Address,
+    }
+
+    impl TestEnv {
+        /// Two markets trading tokens 0 and 2 against token 1
+        ///
+        /// The first market bids 1 of token 1 for each of token 0, and the second market asks 2
+        /// of token 1 for each of token 2.
+        fn new() -> Self {
+            let env = Env::default();
+            let admin = Address::random(&env);
+            let maker = Address::random(&env);
+            let trader = Address::random(&env);
+            let router = env.register_contract(None, DexRouterContract);
+
+            env.mock_all_auths();
+
+            let tokens: std::vec::Vec<_> = ["A", "B", "C"]
+                .into_iter()
+                .map(|symbol| {
+                    let token = env.register_contract(None, test_token::Token);
+                    let symbol = soroban_sdk::String::from_slice(&env, symbol);
+                    test_token::TokenClient::new(&env, &token)
+                        .initialize(&admin, &0, &symbol, &symbol);
+
+                    token
+                })
+                .collect();
+
+            let markets: std::vec::Vec<_> = [(0, OrderSide::Bid), (2, OrderSide::Ask)]
+                .into_iter()
+                .map(|(base, side)| {
+                    let market = env.register_contract(None, DexMarketContract);
+                    let client = DexMarketContractClient::new(&env, &market);
+
+                    client.init(
+                        &admin,
+                        &DexMarketInfo {
+                            base_token: tokens[base].clone(),
+                            quote_token: tokens[1].clone(),
+                            base_min_order_size: 1,
+                            price_tick: 1,
+                            fee_authority: admin.clone(),
+                            maker_fee_bps: 0,
+                            taker_fee_bps: 0,
+                            base_decimals: 0,
+                            quote_decimals: 0,
+                        },
+                    );
+
+                    let (token, price, side) = match side {
+                        OrderSide::Bid => (1, 1 << 32, dex_market::OrderSide::Bid),
+                        OrderSide::Ask => (base, 2 << 32, dex_market::OrderSide::Ask),
+                    };
+
+                    test_token::TokenClient::new(&env, &tokens[token]).mint(&maker, &1_000);
+                    client.place_order(&OrderParams {
+                        side,
+                        size: 1_000,
+                        price,
+                        owner: maker.clone(),
+                        funding: Funding::Wallet,
+                        referrer: Referrer::None,
+                    });
+
+                    market
+                })
+                .collect();
+
+            test_token::TokenClient::new(&env, &tokens[0]).mint(&trader, &100);
+
+            Self {
+                env,
+                tokens,
+                markets,
+                trader,
+                router,
+            }
+        }
+
+        fn router_client(&self) -> DexRouterContractClient {
+            DexRouterContractClient::new(&self.env, &self.router)
+        }
+
+        fn path(&self) -> Vec<Address> {
+            Vec::from_slice(&self.env, &self.markets)
+        }
+
+        fn balances(&self) -> [i128; 3] {
+            [0, 1, 2].map(|token| {
+                test_token::TokenClient::new(&self.env, &self.tokens[token]).balance(&self.trader)
+            })
+        }
+    }
+
+    #[test]
+    fn test_swap_exact_in() {
+        let ctx = TestEnv::new();
+        let router = ctx.router_client();
+
+        assert_eq!(
+            Err(Ok(DexRouterError::SlippageExceeded)),
+            router.try_swap_exact_in(&ctx.trader, &ctx.tokens[0], &ctx.path(), &100, &51)
+        );
+        assert_eq!([100, 0, 0], ctx.balances());
+
+        assert_eq!(
+            50,
+            router.swap_exact_in(&ctx.trader, &ctx.tokens[0], &ctx.path(), &100, &50)
+        );
+        assert_eq!([0, 0, 50], ctx.balances());
+    }
+
+    #[test]
+    fn test_swap_exact_in_thin_book() {
+        let ctx = TestEnv::new();
+        let router = ctx.router_client();
+
+        // the first market only bids for 1_000 of token 0
+        test_token::TokenClient::new(&ctx.env, &ctx.tokens[0]).mint(&ctx.trader, &1_000);
+
+        // the second market asks 2 of token 1 for each of token 2, so 1 of token 1 is left over
+        assert_eq!(
+            50,
+            router.swap_exact_in(&ctx.trader, &ctx.tokens[0], &ctx.path(), &101, &0)
+        );
+        assert_eq!([999, 1, 50], ctx.balances());
+
+        // the first market can only take 899 more
+        assert_eq!(
+            449,
+            router.swap_exact_in(&ctx.trader, &ctx.tokens[0], &ctx.path(), &999, &0)
+        );
+        assert_eq!([100, 2, 499], ctx.balances());
+
+        assert_eq!(
+            Err(Ok(DexRouterError::InsufficientLiquidity)),
+            router.try_swap_exact_in(&ctx.trader, &ctx.tokens[0], &ctx.path(), &100, &0)
+        );
+        assert_eq!([100, 2, 499], ctx.balances());
+    }
+
+    #[test]
+    fn test_swap_exact_out() {
+        let ctx = TestEnv::new();
+        let router = ctx.router_client();
+
+        assert_eq!(
+            Err(Ok(DexRouterError::SlippageExceeded)),
+            router.try_swap_exact_out(&ctx.trader, &ctx.tokens[0], &ctx.path(), &40, &79)
+        );
+        assert_eq!(
+            Err(Ok(DexRouterError::InsufficientLiquidity)),
+            router.try_swap_exact_out(&ctx.trader, &ctx.tokens[0], &ctx.path(), &1_000, &100)
+        );
+        assert_eq!([100, 0, 0], ctx.balances());
+
+        assert_eq!(
+            80,
+            router.swap_exact_out(&ctx.trader, &ctx.tokens[0], &ctx.path(), &40, &80)
+        );
+        assert_eq!([20, 0, 40], ctx.balances());
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        let ctx = TestEnv::new();
+        let router = ctx.router_client();
+
+        assert_eq!(
+            Err(Ok(DexRouterError::InvalidPath)),
+            router.try_swap_exact_in(&ctx.trader, &ctx.tokens[0], &Vec::new(&ctx.env), &100, &0)
+        );
+
+        // the first market doesn't trade token 2
+        assert_eq!(
+            Err(Ok(DexRouterError::InvalidPath)),
+            router.try_swap_exact_in(&ctx.trader, &ctx.tokens[2], &ctx.path(), &100, &0)
+        );
+
+        let mut path = ctx.path();
+        for _ in 0..MAX_HOPS {
+            path.push_back(ctx.markets[1].clone());
+        }
+
+        assert_eq!(
+            Err(Ok(DexRouterError::InvalidPath)),
+            router.try_swap_exact_out(&ctx.trader, &ctx.tokens[0], &path, &100, &100)
+        );
+    }
+}
@@ -1,29 +1,142 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
-use soroban_sdk::{contract, contractimpl, token::Interface, Address, Env, String};
-use soroban_token_sdk::TokenUtils;
+use soroban_sdk::{
+    contract, contracterror, contractimpl, contracttype, panic_with_error, symbol_short,
+    token::Interface, Address, Env, String, Symbol,
+};
+use soroban_token_sdk::{metadata::TokenMetadata, TokenUtils};
 
+/// Storage key for the address allowed to mint tokens and inject failures
+const ADMIN: Symbol = symbol_short!("ADMIN");
+
+/// Storage key prefix for accounts which can't send or receive tokens
+const FROZEN: Symbol = symbol_short!("FROZEN");
+
+/// Storage key for whether every transfer fails
+const FAILING: Symbol = symbol_short!("FAILING");
+
+/// Storage key prefix for the allowance each spender has for an account
+const ALLOWANCE: Symbol = symbol_short!("ALLOWANCE");
+
+#[contracterror]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(u32)]
+pub enum TokenError {
+    NotInitialized = 1,
+    AlreadyInitialized = 2,
+    InvalidAmount = 3,
+    InsufficientBalance = 4,
+    InsufficientAllowance = 5,
+    InvalidExpiration = 6,
+    AccountFrozen = 7,
+    TransfersFailing = 8,
+}
+
+/// An amount a spender can transfer for an account, until the expiration ledger has passed
+#[contracttype]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Allowance {
+    pub amount: i128,
+    pub expiration_ledger: u32,
+}
+
+/// A SEP-41 token for tests, which can be made to fail on demand
 #[contract]
 pub struct Token;
 
 #[contractimpl]
 impl Token {
+    /// Set the admin and the metadata of the token
+    ///
+    /// This can only be done once.
+    pub fn initialize(e: Env, admin: Address, decimals: u32, name: String, symbol: String) {
+        if e.storage().instance().has(&ADMIN) {
+            panic_with_error!(&e, TokenError::AlreadyInitialized);
+        }
+
+        e.storage().instance().set(&ADMIN, &admin);
+        TokenUtils::new(&e).metadata().set_metadata(&TokenMetadata {
+            decimal: decimals,
+            name,
+            symbol,
+        });
+    }
+
+    pub fn admin(e: Env) -> Address {
+        e.storage()
+            .instance()
+            .get(&ADMIN)
+            .unwrap_or_else(|| panic_with_error!(&e, TokenError::NotInitialized))
+    }
+
+    /// Create new tokens, which has to be authorized by the admin
     pub fn mint(e: Env, to: Address, amount: i128) {
-        let balance = Self::balance(e.clone(), to.clone());
-        e.storage().persistent().set(&to, &(balance + amount));
+        let admin = require_admin(&e);
+        check_amount(&e, amount);
+
+        set_balance(&e, &to, Self::balance(e.clone(), to.clone()) + amount);
+
+        TokenUtils::new(&e).events().mint(admin, to, amount);
+    }
+
+    /// Prevent an account from sending or receiving tokens
+    pub fn set_frozen(e: Env, id: Address, frozen: bool) {
+        require_admin(&e);
+
+        let key = (FROZEN, id);
+
+        match frozen {
+            true => e.storage().persistent().set(&key, &()),
+            false => e.storage().persistent().remove(&key),
+        }
+    }
+
+    pub fn is_frozen(e: Env, id: Address) -> bool {
+        e.storage().persistent().has(&(FROZEN, id))
+    }
+
+    /// Make every transfer fail, whoever sends or receives it
+    pub fn set_failing(e: Env, failing: bool) {
+        require_admin(&e);
+
+        match failing {
+            true => e.storage().instance().set(&FAILING, &()),
+            false => e.storage().instance().remove(&FAILING),
+        }
+    }
 
-        TokenUtils::new(&e).events().mint(to.clone(), to, amount);
+    pub fn is_failing(e: Env) -> bool {
+        e.storage().instance().has(&FAILING)
     }
 }
 
 #[contractimpl]
 impl Interface for Token {
-    fn allowance(_e: Env, _from: Address, _spender: Address) -> i128 {
-        todo!()
+    fn allowance(e: Env, from: Address, spender: Address) -> i128 {
+        allowance(&e, &from, &spender).amount
     }
 
-    fn approve(_e: Env, _from: Address, _spender: Address, _amount: i128, _expiration_ledger: u32) {
-        todo!()
+    fn approve(e: Env, from: Address, spender: Address, amount: i128, expiration_ledger: u32) {
+        from.require_auth();
+        check_amount(&e, amount);
+
+        if amount > 0 && expiration_ledger < e.ledger().sequence() {
+            panic_with_error!(&e, TokenError::InvalidExpiration);
+        }
+
+        set_allowance(
+            &e,
+            &from,
+            &spender,
+            &Allowance {
+                amount,
+                expiration_ledger,
+            },
+        );
+
+        TokenUtils::new(&e)
+            .events()
+            .approve(from, spender, amount, expiration_ledger);
     }
 
     fn balance(e: Env, id: Address) -> i128 {
@@ -40,45 +153,256 @@ impl Interface for Token {
     fn transfer(e: Env, from: Address, to: Address, amount: i128) {
         from.require_auth();
 
-        let from_balance = Self::balance(e.clone(), from.clone());
-        let to_balance = Self::balance(e.clone(), to.clone());
-
-        if from_balance < amount {
-            panic!(
-                "insufficient balance, has {} but needs {}",
-                from_balance, amount
-            );
-        }
+        move_balance(&e, &from, &to, amount);
+    }
 
-        e.storage()
-            .persistent()
-            .set(&from, &(from_balance - amount));
-        e.storage().persistent().set(&to, &(to_balance + amount));
+    fn transfer_from(e: Env, spender: Address, from: Address, to: Address, amount: i128) {
+        spender.require_auth();
 
-        TokenUtils::new(&e).events().transfer(from, to, amount);
+        spend_allowance(&e, &from, &spender, amount);
+        move_balance(&e, &from, &to, amount);
     }
 
-    fn transfer_from(_e: Env, _spender: Address, _from: Address, _to: Address, _amount: i128) {
-        todo!()
-    }
+    fn burn(e: Env, from: Address, amount: i128) {
+        from.require_auth();
 
-    fn burn(_e: Env, _from: Address, _amount: i128) {
-        todo!()
+        burn_balance(&e, &from, amount);
     }
 
-    fn burn_from(_e: Env, _spender: Address, _from: Address, _amount: i128) {
-        todo!()
+    fn burn_from(e: Env, spender: Address, from: Address, amount: i128) {
+        spender.require_auth();
+
+        spend_allowance(&e, &from, &spender, amount);
+        burn_balance(&e, &from, amount);
     }
 
-    fn decimals(_e: Env) -> u32 {
-        0
+    fn decimals(e: Env) -> u32 {
+        metadata(&e).decimal
     }
 
     fn name(e: Env) -> String {
-        String::from_slice(&e, "MockToken")
+        metadata(&e).name
     }
 
     fn symbol(e: Env) -> String {
-        String::from_slice(&e, "MockToken")
+        metadata(&e).symbol
+    }
+}
+
+fn require_admin(e: &Env) -> Address {
+    let admin = Token::admin(e.clone());
+    admin.require_auth();
+
+    admin
+}
+
+fn metadata(e: &Env) -> TokenMetadata {
+    if !e.storage().instance().has(&ADMIN) {
+        panic_with_error!(e, TokenError::NotInitialized);
+    }
+
+    TokenUtils::new(e).metadata().get_metadata()
+}
+
+fn check_amount(e: &Env, amount: i128) {
+    if amount < 0 {
+        panic_with_error!(e, TokenError::InvalidAmount);
+    }
+}
+
+fn set_balance(e: &Env, id: &Address, balance: i128) {
+    e.storage().persistent().set(id, &balance);
+}
+
+/// Take tokens from an account, failing if it doesn't have enough
+fn spend_balance(e: &Env, id: &Address, amount: i128) {
+    let balance = Token::balance(e.clone(), id.clone());
+
+    if balance < amount {
+        panic_with_error!(e, TokenError::InsufficientBalance);
+    }
+
+    set_balance(e, id, balance - amount);
+}
+
+fn move_balance(e: &Env, from: &Address, to: &Address, amount: i128) {
+    check_amount(e, amount);
+
+    if Token::is_failing(e.clone()) {
+        panic_with_error!(e, TokenError::TransfersFailing);
+    }
+
+    if Token::is_frozen(e.clone(), from.clone()) || Token::is_frozen(e.clone(), to.clone()) {
+        panic_with_error!(e, TokenError::AccountFrozen);
+    }
+
+    spend_balance(e, from, amount);
+    set_balance(e, to, Token::balance(e.clone(), to.clone()) + amount);
+
+    TokenUtils::new(e)
+        .events()
+        .transfer(from.clone(), to.clone(), amount);
+}
+
+fn burn_balance(e: &Env, from: &Address, amount: i128) {
+    check_amount(e, amount);
+
+    if Token::is_frozen(e.clone(), from.clone()) {
+        panic_with_error!(e, TokenError::AccountFrozen);
+    }
+
+    spend_balance(e, from, amount);
+
+    TokenUtils::new(e).events().burn(from.clone(), amount);
+}
+
+/// The allowance a spender has for an account, which is nothing once it has expired
+fn allowance(e: &Env, from: &Address, spender: &Address) -> Allowance {
+    e.storage()
+        .persistent()
+        .get::<_, Allowance>(&(ALLOWANCE, from.clone(), spender.clone()))
+        .filter(|allowance| allowance.expiration_ledger >= e.ledger().sequence())
+        .unwrap_or_default()
+}
+
+fn set_allowance(e: &Env, from: &Address, spender: &Address, allowance: &Allowance) {
+    let key = (ALLOWANCE, from.clone(), spender.clone());
+
+    match allowance.amount {
+        0 => e.storage().persistent().remove(&key),
+        _ => e.storage().persistent().set(&key, allowance),
+    }
+}
+
+fn spend_allowance(e: &Env, from: &Address, spender: &Address, amount: i128) {
+    check_amount(e, amount);
+
+    let allowance = allowance(e, from, spender);
+
+    if allowance.amount < amount {
+        panic_with_error!(e, TokenError::InsufficientAllowance);
+    }
+
+    set_allowance(
+        e,
+        from,
+        spender,
+        &Allowance {
+            amount: allowance.amount - amount,
+            ..allowance
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use soroban_sdk::testutils::{Address as _, Ledger};
+
+    use super::*;
+
+    fn new_token(env: &Env) -> TokenClient {
+        let token = TokenClient::new(env, &env.register_contract(None, Token));
+
+        env.mock_all_auths();
+        token.initialize(
+            &Address::random(env),
+            &7,
+            &String::from_slice(env, "Test Token"),
+            &String::from_slice(env, "TEST"),
+        );
+
+        token
+    }
+
+    #[test]
+    fn test_metadata_and_mint() {
+        let env = Env::default();
+        let token = new_token(&env);
+        let user = Address::random(&env);
+
+        assert_eq!(7, token.decimals());
+        assert_eq!(String::from_slice(&env, "Test Token"), token.name());
+        assert_eq!(String::from_slice(&env, "TEST"), token.symbol());
+
+        token.mint(&user, &100);
+        let minter = env.auths()[0].0.clone();
+
+        assert_eq!(token.admin(), minter);
+        assert_eq!(100, token.balance(&user));
+
+        assert_eq!(
+            Err(Ok(TokenError::AlreadyInitialized.into())),
+            token.try_initialize(&user, &0, &token.name(), &token.symbol())
+        );
+    }
+
+    #[test]
+    fn test_allowances() {
+        let env = Env::default();
+        let token = new_token(&env);
+        let owner = Address::random(&env);
+        let spender = Address::random(&env);
+
+        token.mint(&owner, &100);
+        token.approve(&owner, &spender, &50, &10);
+        assert_eq!(50, token.allowance(&owner, &spender));
+
+        token.transfer_from(&spender, &owner, &spender, &30);
+        token.burn_from(&spender, &owner, &10);
+        assert_eq!(10, token.allowance(&owner, &spender));
+        assert_eq!(60, token.balance(&owner));
+        assert_eq!(30, token.balance(&spender));
+
+        assert_eq!(
+            Err(Ok(TokenError::InsufficientAllowance.into())),
+            token.try_transfer_from(&spender, &owner, &spender, &11)
+        );
+
+        // an allowance can be used up to and including its expiration ledger
+        env.ledger().with_mut(|ledger| ledger.sequence_number = 10);
+        assert_eq!(10, token.allowance(&owner, &spender));
+
+        env.ledger().with_mut(|ledger| ledger.sequence_number = 11);
+        assert_eq!(0, token.allowance(&owner, &spender));
+        assert_eq!(
+            Err(Ok(TokenError::InsufficientAllowance.into())),
+            token.try_burn_from(&spender, &owner, &1)
+        );
+        assert_eq!(
+            Err(Ok(TokenError::InvalidExpiration.into())),
+            token.try_approve(&owner, &spender, &1, &10)
+        );
+    }
+
+    #[test]
+    fn test_failure_injection() {
+        let env = Env::default();
+        let token = new_token(&env);
+        let users = [Address::random(&env), Address::random(&env)];
+
+        token.mint(&users[0], &100);
+        token.burn(&users[0], &40);
+
+        assert_eq!(
+            Err(Ok(TokenError::InsufficientBalance.into())),
+            token.try_transfer(&users[0], &users[1], &61)
+        );
+
+        token.set_frozen(&users[1], &true);
+        assert_eq!(
+            Err(Ok(TokenError::AccountFrozen.into())),
+            token.try_transfer(&users[0], &users[1], &10)
+        );
+
+        token.set_frozen(&users[1], &false);
+        token.set_failing(&true);
+        assert_eq!(
+            Err(Ok(TokenError::TransfersFailing.into())),
+            token.try_transfer(&users[0], &users[1], &10)
+        );
+
+        token.set_failing(&false);
+        token.transfer(&users[0], &users[1], &10);
+        assert_eq!([50, 10], users.clone().map(|user| token.balance(&user)));
     }
 }
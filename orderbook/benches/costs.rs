@@ -0,0 +1,330 @@
+//! Resource costs of order book operations
+//!
+//! Measures the CPU instructions, memory and ledger access of each operation against books
+//! of increasing depth, using the budget and recording footprint of the test environment.
+//! The results are printed as a table and written as JSON to `orderbook-costs.json` in the
+//! cargo target tmp directory, or to the path given as the first argument.
+//!
+//! ```bash
+//! cargo bench -p orderbook --features testutils
+//! ```
+
+use std::{collections::BTreeMap, fmt::Write as _, rc::Rc};
+
+use orderbook::{OrderBook, OrderId, OrderParams, OrderSide};
+use soroban_env_host::storage::AccessType;
+use soroban_sdk::{
+    contract, contractimpl,
+    xdr::{LedgerKey, WriteXdr},
+    Address, Env, Map,
+};
+
+const PREFIX: u16 = 0xBE4C;
+const DEPTHS: &[u32] = &[1, 10, 50, 100, 250];
+
+/// An empty contract, which provides the storage context for the book
+#[contract]
+struct BenchContract;
+
+#[contractimpl]
+impl BenchContract {}
+
+/// The resources used by a single operation
+struct Cost {
+    name: &'static str,
+    depth: u32,
+    cpu_insns: u64,
+    mem_bytes: u64,
+    read_entries: u32,
+    write_entries: u32,
+    read_bytes: u64,
+    write_bytes: u64,
+}
+
+struct Bench {
+    env: Env,
+    contract_id: Address,
+}
+
+impl Bench {
+    fn new() -> Self {
+        let env = Env::default();
+        env.budget().reset_unlimited();
+
+        Self {
+            contract_id: env.register_contract(None, BenchContract),
+            env,
+        }
+    }
+
+    fn with_book<R>(&self, f: impl FnOnce(&OrderBook<u32>) -> R) -> R {
+        self.env
+            .as_contract(&self.contract_id, || f(&OrderBook::open(&self.env, PREFIX)))
+    }
+
+    fn place(&self, side: OrderSide, price: u64, size: u128) -> Option<OrderId> {
+        self.with_book(|book| {
+            book.place_order(
+                &OrderParams {
+                    side,
+                    price,
+                    size,
+                    details: 0,
+                },
+                |_| {},
+            )
+            .unwrap()
+            .posted_id
+        })
+    }
+
+    /// Serialized size of every ledger entry currently in storage
+    fn entry_sizes(&self) -> BTreeMap<Rc<LedgerKey>, u64> {
+        let budget = self.env.host().budget_cloned();
+
+        self.env
+            .host()
+            .with_mut_storage(|storage| {
+                Ok(storage
+                    .map
+                    .iter(&budget)?
+                    .filter_map(|(key, entry)| {
+                        let (entry, _) = entry.as_ref()?;
+                        let size = entry.to_xdr().unwrap().len() as u64;
+
+                        Some((key.clone(), size))
+                    })
+                    .collect())
+            })
+            .unwrap()
+    }
+
+    /// Run a single operation against the book and record the resources it used
+    fn measure(&self, name: &'static str, depth: u32, op: impl FnOnce(&OrderBook<u32>)) -> Cost {
+        let sizes_before = self.entry_sizes();
+
+        self.env
+            .host()
+            .with_mut_storage(|storage| {
+                storage.footprint = Default::default();
+                Ok(())
+            })
+            .unwrap();
+
+        self.env.budget().reset_unlimited();
+        self.with_book(op);
+
+        let cpu_insns = self.env.budget().cpu_instruction_cost();
+        let mem_bytes = self.env.budget().memory_bytes_cost();
+
+        let sizes_after = self.entry_sizes();
+        let budget = self.env.host().budget_cloned();
+
+        let mut cost = Cost {
+            name,
+            depth,
+            cpu_insns,
+            mem_bytes,
+            read_entries: 0,
+            write_entries: 0,
+            read_bytes: 0,
+            write_bytes: 0,
+        };
+
+        self.env
+            .host()
+            .with_mut_storage(|storage| {
+                for (key, access) in storage.footprint.0.iter(&budget)? {
+                    cost.read_entries += 1;
+                    cost.read_bytes += sizes_before.get(key).copied().unwrap_or(0);
+
+                    if let AccessType::ReadWrite = access {
+                        cost.write_entries += 1;
+                        cost.write_bytes += sizes_after.get(key).copied().unwrap_or(0);
+                    }
+                }
+
+                Ok(())
+            })
+            .unwrap();
+
+        cost
+    }
+}
+
+/// Place a bid behind a book with `depth` ask levels
+fn place_new_level(depth: u32) -> Cost {
+    let bench = Bench::new();
+
+    for i in 0..depth {
+        bench.place(OrderSide::Ask, 1_000 + i as u64, 10);
+    }
+
+    bench.measure("place_new_level", depth, |book| {
+        book.place_order(
+            &OrderParams {
+                side: OrderSide::Bid,
+                price: 500,
+                size: 10,
+                details: 0,
+            },
+            |_| {},
+        )
+        .unwrap();
+    })
+}
+
+/// Place an ask behind `depth` asks queued at the same price
+fn place_queued(depth: u32) -> Cost {
+    let bench = Bench::new();
+
+    for _ in 0..depth {
+        bench.place(OrderSide::Ask, 1_000, 10);
+    }
+
+    bench.measure("place_queued", depth, |book| {
+        book.place_order(
+            &OrderParams {
+                side: OrderSide::Ask,
+                price: 1_000,
+                size: 10,
+                details: 0,
+            },
+            |_| {},
+        )
+        .unwrap();
+    })
+}
+
+/// Place a bid which fills against all of `depth` ask levels
+fn sweep_levels(depth: u32) -> Cost {
+    let bench = Bench::new();
+
+    for i in 0..depth {
+        bench.place(OrderSide::Ask, 1_000 + i as u64, 10);
+    }
+
+    bench.measure("sweep_levels", depth, |book| {
+        book.place_order(
+            &OrderParams {
+                side: OrderSide::Bid,
+                price: 1_000 + depth as u64,
+                size: 10 * depth as u128,
+                details: 0,
+            },
+            |_| {},
+        )
+        .unwrap();
+    })
+}
+
+/// Cancel the order in the middle of a book with `depth` levels
+fn cancel(depth: u32) -> Cost {
+    let bench = Bench::new();
+
+    let orders: Vec<_> = (0..depth)
+        .map(|i| bench.place(OrderSide::Ask, 1_000 + i as u64, 10).unwrap())
+        .collect();
+
+    let order = orders[orders.len() / 2].clone();
+
+    bench.measure("cancel", depth, |book| {
+        book.cancel_order(&order).unwrap();
+    })
+}
+
+/// Consume the fill events of `depth` orders after they were swept
+fn consume_events(depth: u32) -> Cost {
+    let bench = Bench::new();
+
+    let orders: Vec<_> = (0..depth)
+        .map(|i| bench.place(OrderSide::Ask, 1_000 + i as u64, 10).unwrap())
+        .collect();
+
+    bench.place(OrderSide::Bid, 1_000 + depth as u64, 10 * depth as u128);
+
+    let mut to_consume = Map::new(&bench.env);
+    for order in orders {
+        to_consume.set(order, 1);
+    }
+
+    bench.measure("consume_events", depth, |book| {
+        book.consume_events(to_consume);
+    })
+}
+
+fn to_json(costs: &[Cost]) -> String {
+    let mut json = String::from("[\n");
+
+    for (i, cost) in costs.iter().enumerate() {
+        let separator = if i + 1 < costs.len() { "," } else { "" };
+
+        writeln!(
+            json,
+            "  {{\"name\": \"{}\", \"depth\": {}, \"cpu_insns\": {}, \"mem_bytes\": {}, \
+             \"read_entries\": {}, \"write_entries\": {}, \"read_bytes\": {}, \
+             \"write_bytes\": {}}}{separator}",
+            cost.name,
+            cost.depth,
+            cost.cpu_insns,
+            cost.mem_bytes,
+            cost.read_entries,
+            cost.write_entries,
+            cost.read_bytes,
+            cost.write_bytes,
+        )
+        .unwrap();
+    }
+
+    json.push_str("]\n");
+    json
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, anything else is the output path
+    let output = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|| {
+            std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("orderbook-costs.json")
+        });
+
+    let scenarios: &[fn(u32) -> Cost] = &[
+        place_new_level,
+        place_queued,
+        sweep_levels,
+        cancel,
+        consume_events,
+    ];
+
+    let mut costs = vec![];
+
+    println!(
+        "{:<16} {:>6} {:>12} {:>12} {:>6} {:>6} {:>10} {:>10}",
+        "operation", "depth", "cpu", "mem", "reads", "writes", "read B", "write B"
+    );
+
+    for scenario in scenarios {
+        for depth in DEPTHS {
+            let cost = scenario(*depth);
+
+            println!(
+                "{:<16} {:>6} {:>12} {:>12} {:>6} {:>6} {:>10} {:>10}",
+                cost.name,
+                cost.depth,
+                cost.cpu_insns,
+                cost.mem_bytes,
+                cost.read_entries,
+                cost.write_entries,
+                cost.read_bytes,
+                cost.write_bytes
+            );
+
+            costs.push(cost);
+        }
+    }
+
+    std::fs::write(&output, to_json(&costs)).unwrap();
+    println!("\nreport written to {}", output.display());
+}
@@ -0,0 +1,166 @@
+#![no_main]
+
+use std::collections::BTreeMap;
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use orderbook::{OrderBook, OrderId, OrderParams, OrderSide};
+use soroban_sdk::{contract, contractimpl, vec, BytesN, Env, IntoVal, Map, TryFromVal};
+
+const PREFIX: u16 = 0xF022;
+
+/// An empty contract, which provides the storage context for the book
+#[contract]
+struct FuzzContract;
+
+#[contractimpl]
+impl FuzzContract {}
+
+#[derive(Arbitrary, Debug)]
+enum Side {
+    Bid,
+    Ask,
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    /// Place an order, prices are kept small so orders collide and match often
+    Place { side: Side, price: u8, size: u16 },
+
+    /// Cancel one of the previously placed orders
+    Cancel { order: u8 },
+
+    /// Cancel an arbitrary id, which may be malformed
+    CancelRaw { bytes: [u8; 16] },
+
+    /// Consume events for one of the previously placed orders
+    Consume { order: u8, count: u8 },
+
+    /// Consume events for an arbitrary id, which may be malformed
+    ConsumeRaw { bytes: [u8; 16], count: u8 },
+}
+
+/// Decode an id the same way the contract host does for contract arguments
+fn raw_id(env: &Env, bytes: &[u8; 16]) -> OrderId {
+    let raw = vec![env, BytesN::from_array(env, bytes)];
+    OrderId::try_from_val(env, &raw.into_val(env)).unwrap()
+}
+
+fn best_live_price(book: &OrderBook<u32>, side: OrderSide) -> Option<u64> {
+    book.orders(side)
+        .into_iter()
+        .find(|id| book.get_order(id).map(|o| o.size > 0).unwrap_or(false))
+        .map(|id| id.price())
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let env = Env::default();
+    env.budget().reset_unlimited();
+
+    let contract_id = env.register_contract(None, FuzzContract);
+
+    env.as_contract(&contract_id, || {
+        let book = OrderBook::<u32>::open(&env, PREFIX);
+
+        let mut placed: Vec<OrderId> = Vec::new();
+        let mut resting: BTreeMap<OrderId, u128> = BTreeMap::new();
+
+        for (tag, op) in ops.into_iter().enumerate() {
+            match op {
+                Op::Place { side, price, size } => {
+                    let side = match side {
+                        Side::Bid => OrderSide::Bid,
+                        Side::Ask => OrderSide::Ask,
+                    };
+
+                    let params = OrderParams {
+                        side,
+                        price: price as u64,
+                        size: size as u128,
+                        details: tag as u32,
+                    };
+
+                    let mut matched = 0;
+                    let summary = book.place_order(&params, |entry| {
+                        match side {
+                            OrderSide::Bid => assert!(entry.price <= params.price),
+                            OrderSide::Ask => assert!(entry.price >= params.price),
+                        }
+
+                        let remaining = resting.get_mut(&entry.id).unwrap();
+                        *remaining -= entry.size;
+                        matched += entry.size;
+                    })
+                    .unwrap();
+
+                    assert_eq!(params.size, matched + summary.posted_size);
+
+                    if let Some(id) = summary.posted_id {
+                        assert_eq!(side, id.side());
+                        resting.insert(id.clone(), summary.posted_size);
+                        placed.push(id);
+                    }
+                }
+
+                Op::Cancel { order } => {
+                    let Some(id) = placed.get(order as usize) else {
+                        continue;
+                    };
+
+                    book.cancel_order(id).unwrap();
+                    resting.remove(id);
+                }
+
+                Op::CancelRaw { bytes } => {
+                    let id = raw_id(&env, &bytes);
+
+                    if book.cancel_order(&id).is_ok() {
+                        resting.remove(&id);
+                    }
+                }
+
+                Op::Consume { order, count } => {
+                    let Some(id) = placed.get(order as usize) else {
+                        continue;
+                    };
+
+                    let mut orders = Map::new(&env);
+                    orders.set(id.clone(), count as u32);
+
+                    let consumed = book.consume_events(orders);
+                    assert!(consumed.len() <= count as u32);
+
+                    if book.get_order(id).is_none() {
+                        resting.remove(id);
+                    }
+                }
+
+                Op::ConsumeRaw { bytes, count } => {
+                    let id = raw_id(&env, &bytes);
+
+                    let mut orders = Map::new(&env);
+                    orders.set(id.clone(), count as u32);
+
+                    book.consume_events(orders);
+
+                    if book.get_order(&id).is_none() {
+                        resting.remove(&id);
+                    }
+                }
+            }
+
+            // every order the model knows about is still in the book with the expected size
+            for (id, size) in resting.iter() {
+                assert_eq!(Some(*size), book.get_order(id).map(|o| o.size));
+            }
+
+            // the live part of the book is never crossed
+            if let (Some(bid), Some(ask)) = (
+                best_live_price(&book, OrderSide::Bid),
+                best_live_price(&book, OrderSide::Ask),
+            ) {
+                assert!(bid < ask, "crossed book: bid {bid} >= ask {ask}");
+            }
+        }
+    });
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use orderbook::{OrderId, OrderIdError};
+use soroban_sdk::{vec, BytesN, Env, IntoVal, TryFromVal};
+
+fuzz_target!(|bytes: [u8; 16]| {
+    let env = Env::default();
+    let raw = BytesN::from_array(&env, &bytes);
+
+    // ids arriving as contract arguments skip `try_from`, so decoding them must not trap
+    let unchecked = OrderId::try_from_val(&env, &vec![&env, raw.clone()].into_val(&env)).unwrap();
+
+    match OrderId::try_from(raw) {
+        Ok(id) => {
+            let side = id.side();
+            let rebuilt = OrderId::new(&env, id.prefix(), side, id.price(), id.id());
+
+            assert_eq!(Ok(side), unchecked.try_side());
+            assert_eq!(rebuilt, id);
+        }
+
+        Err(OrderIdError::InvalidReserved) => assert_ne!(0, bytes[2]),
+        Err(OrderIdError::InvalidSide) => {
+            assert!(bytes[3] > 1);
+            assert_eq!(Err(OrderIdError::InvalidSide), unchecked.try_side());
+        }
+
+        Err(OrderIdError::WrongBook) => unreachable!("decoding does not know about books"),
+    }
+});
@@ -10,6 +10,12 @@ pub use orders::*;
 use soroban_sdk::{contracttype, Env, IntoVal, Map, TryFromVal, Val, Vec};
 use storage::*;
 
+/// The highest price an order can be posted at
+///
+/// Price queues are keyed by the order id with the most significant byte of the price cleared,
+/// so only prices which fit in the remaining 7 bytes are distinct.
+pub const MAX_PRICE: u64 = (1 << 56) - 1;
+
 /// A general purpose order book
 pub struct OrderBook<T>
 where
@@ -44,15 +50,82 @@ where
         self.book().orders(side)
     }
 
-    pub fn cancel_order(&self, id: &OrderId) {
+    /// Remove an order from the book
+    ///
+    /// Fails if the id is malformed or belongs to a different book.
+    pub fn cancel_order(&self, id: &OrderId) -> Result<(), OrderIdError> {
+        self.check_id(id)?;
         self.book().remove_order(id);
+
+        Ok(())
     }
 
+    /// Match an order against the book, and post the remainder
+    ///
+    /// Fails without matching anything if the price is above [MAX_PRICE].
     pub fn place_order(
+        &self,
+        params: &OrderParams<T>,
+        on_match: impl FnMut(&OrderEntry<OrderId, T>),
+    ) -> Result<OrderSummary<OrderId>, OrderError> {
+        if params.price > MAX_PRICE {
+            return Err(OrderError::PriceTooLarge);
+        }
+
+        let amount_to_post = self.match_order(params, on_match);
+
+        let mut posted_id = None;
+        if amount_to_post > 0 {
+            posted_id = Some(self.book.place_order(
+                params.side,
+                params.price,
+                amount_to_post,
+                &params.details,
+            ));
+        }
+
+        Ok(OrderSummary {
+            posted_id,
+            posted_size: amount_to_post,
+        })
+    }
+
+    /// Post an order to the book without matching it
+    ///
+    /// The book is left crossed if the order would have matched, so this is only for moving
+    /// orders which were already resting in a book that wasn't crossed. Fails if the price is
+    /// above [MAX_PRICE].
+    pub fn post_order(
+        &self,
+        side: OrderSide,
+        price: u64,
+        size: u128,
+        details: &T,
+    ) -> Result<OrderId, OrderError> {
+        if price > MAX_PRICE {
+            return Err(OrderError::PriceTooLarge);
+        }
+
+        Ok(self.book.place_order(side, price, size, details))
+    }
+
+    /// Match an order against the book without posting the remainder
+    ///
+    /// Since nothing is posted, the price can be anything. Returns the size of the order which
+    /// could not be filled.
+    pub fn fill_order(
+        &self,
+        params: &OrderParams<T>,
+        on_match: impl FnMut(&OrderEntry<OrderId, T>),
+    ) -> u128 {
+        self.match_order(params, on_match)
+    }
+
+    fn match_order(
         &self,
         params: &OrderParams<T>,
         mut on_match: impl FnMut(&OrderEntry<OrderId, T>),
-    ) -> OrderSummary<OrderId> {
+    ) -> u128 {
         let matchable = self.book().orders(params.side.opposite());
         let order_events = self.book().order_events();
         let mut amount_to_post = params.size;
@@ -97,28 +170,41 @@ where
             }
         }
 
-        let mut posted_id = None;
-        if amount_to_post > 0 {
-            posted_id = Some(self.book.place_order(
-                params.side,
-                params.price,
-                amount_to_post,
-                &params.details,
-            ));
-        }
-
-        OrderSummary {
-            posted_id,
-            posted_size: amount_to_post,
-        }
+        amount_to_post
     }
 
     pub fn events(&self) -> Map<OrderId, Vec<OrderEvent>> {
         self.book().order_events().all()
     }
 
+    /// The pending events for a single order
+    pub fn order_events(&self, id: &OrderId) -> Vec<OrderEvent> {
+        self.book().order_events().get(id)
+    }
+
+    /// Consume pending events for a set of orders
+    ///
+    /// Ids which are malformed or belong to a different book are ignored.
     pub fn consume_events(&self, orders: Map<OrderId, u32>) -> Vec<(OrderId, OrderEvent)> {
-        self.book().order_events().consume(orders)
+        let mut valid_orders = Map::new(orders.env());
+
+        for (id, count) in orders {
+            if self.check_id(&id).is_ok() {
+                valid_orders.set(id, count);
+            }
+        }
+
+        self.book().order_events().consume(valid_orders)
+    }
+
+    /// Check that an order id is well formed and belongs to this book
+    pub fn check_id(&self, id: &OrderId) -> Result<(), OrderIdError> {
+        let id = OrderId::try_from(id.as_ref().clone())?;
+
+        match id.prefix() == self.book.prefix() {
+            true => Ok(()),
+            false => Err(OrderIdError::WrongBook),
+        }
     }
 
     fn book(&self) -> &impl Book<T> {
@@ -144,7 +230,7 @@ pub trait OrderEventMap {
 }
 
 /// The side of the book an order can be placed on
-#[contracttype]
+#[contracttype(export = false)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub enum OrderSide {
@@ -181,6 +267,13 @@ where
     pub details: T,
 }
 
+/// The reasons an order can be rejected by the book
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OrderError {
+    /// The price is above [MAX_PRICE]
+    PriceTooLarge,
+}
+
 /// The summary provided after attempting to post an order
 #[derive(Clone)]
 pub struct OrderSummary<Id>
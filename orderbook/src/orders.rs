@@ -10,8 +10,8 @@ use crate::OrderSide;
 ///
 /// Structure:
 ///     - 2 bytes: prefix (for contract storage namespacing)
-///     - 1 byte: order side
 ///     - 1 byte: reserved
+///     - 1 byte: order side
 ///     - 8 bytes: price (lists orders)
 ///     - 4 bytes: order id (a specific order entry)
 #[contracttype]
@@ -30,16 +30,31 @@ impl OrderId {
         Self(BytesN::from_array(env, &bytes))
     }
 
+    /// The side of the book the order is on
+    ///
+    /// # Panics
+    ///
+    /// If the id is malformed, which can only happen for ids that were neither created
+    /// by the book nor checked with [OrderId::try_from].
     pub fn side(&self) -> OrderSide {
+        self.try_side().expect("malformed order id")
+    }
+
+    /// The side of the book the order is on, or an error if the side byte is invalid
+    pub fn try_side(&self) -> Result<OrderSide, OrderIdError> {
         match self.0.to_array()[3] {
-            0 => OrderSide::Bid,
-            1 => OrderSide::Ask,
-            _ => unreachable!(),
+            0 => Ok(OrderSide::Bid),
+            1 => Ok(OrderSide::Ask),
+            _ => Err(OrderIdError::InvalidSide),
         }
     }
 
+    pub fn prefix(&self) -> u16 {
+        u16::from_be_bytes(self.0.to_array()[0..2].try_into().unwrap())
+    }
+
     pub fn book_key(&self) -> Bytes {
-        Bytes::from_slice(&self.0.env(), &self.0.to_array()[0..3])
+        Bytes::from_slice(self.0.env(), &self.0.to_array()[0..3])
     }
 
     pub fn price(&self) -> u64 {
@@ -47,7 +62,7 @@ impl OrderId {
     }
 
     pub fn price_key(&self) -> Bytes {
-        let mut bytes = Bytes::from_slice(&self.0.env(), &self.0.to_array()[0..12]);
+        let mut bytes = Bytes::from_slice(self.0.env(), &self.0.to_array()[0..12]);
         bytes.set(4, 0);
 
         bytes
@@ -58,6 +73,22 @@ impl OrderId {
     }
 }
 
+impl TryFrom<BytesN<16>> for OrderId {
+    type Error = OrderIdError;
+
+    /// Decode an order id from its raw bytes, checking that the layout is well formed
+    fn try_from(value: BytesN<16>) -> Result<Self, Self::Error> {
+        if value.to_array()[2] != 0 {
+            return Err(OrderIdError::InvalidReserved);
+        }
+
+        let id = Self(value);
+        id.try_side()?;
+
+        Ok(id)
+    }
+}
+
 impl AsRef<BytesN<16>> for OrderId {
     fn as_ref(&self) -> &BytesN<16> {
         &self.0
@@ -73,6 +104,19 @@ impl Debug for OrderId {
     }
 }
 
+/// The reasons an [OrderId] can be rejected
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OrderIdError {
+    /// The side byte is not a known [OrderSide]
+    InvalidSide,
+
+    /// The reserved byte is not zero
+    InvalidReserved,
+
+    /// The id belongs to a book with a different prefix
+    WrongBook,
+}
+
 /// An event indicating some action needs to be completed
 #[contracttype]
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -80,3 +124,33 @@ pub enum OrderEvent {
     /// The order has been partially filled
     Fill(u128),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_malformed_ids() {
+        let env = Env::default();
+        let id = OrderId::new(&env, 0xBEEF, OrderSide::Ask, 1 << 32, 7);
+
+        assert_eq!(Ok(id.clone()), OrderId::try_from(id.as_ref().clone()));
+        assert_eq!(0xBEEF, id.prefix());
+
+        let mut bytes = id.as_ref().to_array();
+        bytes[3] = 2;
+
+        assert_eq!(
+            Err(OrderIdError::InvalidSide),
+            OrderId::try_from(BytesN::from_array(&env, &bytes))
+        );
+
+        let mut bytes = id.as_ref().to_array();
+        bytes[2] = 1;
+
+        assert_eq!(
+            Err(OrderIdError::InvalidReserved),
+            OrderId::try_from(BytesN::from_array(&env, &bytes))
+        );
+    }
+}
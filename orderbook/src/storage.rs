@@ -23,6 +23,11 @@ impl BookStorage {
         }
     }
 
+    /// The prefix used for all keys of this book
+    pub fn prefix(&self) -> u16 {
+        self.prefix
+    }
+
     fn storage(&self) -> Persistent {
         self.env.storage().persistent()
     }
@@ -73,6 +78,12 @@ impl BookStorage {
     }
 
     fn cleanup_order(&self, order: &OrderId, force_remove: bool) {
+        // bids and asks at the same price share a queue, so an id for an order which
+        // does not exist could otherwise remove the entry of an order on the other side
+        if !self.storage().has(order) {
+            return;
+        }
+
         let mut queue = self.get_price_queue(order.price());
         let current_size = queue.get(order.id()).unwrap_or(0);
 
@@ -110,7 +121,7 @@ where
         let size = queue.get(id.id())?;
 
         self.storage()
-            .get::<OrderId, T>(&id)
+            .get::<OrderId, T>(id)
             .map(|details| OrderEntry {
                 id: id.clone(),
                 price: id.price(),
@@ -228,7 +239,7 @@ impl OrderEventMap for OrderEventQueue {
                     map.remove(order.clone());
                     self.inner.cleanup_order(&order, false);
                 }
-                _ => _ = map.set(order.clone(), events),
+                _ => map.set(order.clone(), events),
             }
         }
 
@@ -360,14 +371,14 @@ mod tests {
             let book = Self::book(&env);
 
             let mut orders = book.orders(OrderSide::Bid).into_iter();
-            orders.next().map(|id| id.clone())
+            orders.next()
         }
 
         pub fn top_ask(env: Env) -> Option<OrderId> {
             let book = Self::book(&env);
 
             let mut orders = book.orders(OrderSide::Ask).into_iter();
-            orders.next().map(|id| id.clone())
+            orders.next()
         }
     }
 
@@ -445,4 +456,18 @@ mod tests {
             client.remove_order(&ask);
         }
     }
+
+    #[test]
+    fn removing_missing_order_keeps_other_side() {
+        let env = TestEnv::new();
+        let client = env.client();
+
+        let ask = client.place_ask(&100, &10);
+        let missing_bid = OrderId::new(&env.env, 0xBEEF, OrderSide::Bid, 100, ask.id());
+
+        client.remove_order(&missing_bid);
+
+        assert_eq!(Some(10), client.get_order_size(&ask));
+        assert_eq!(Some(ask), client.top_ask());
+    }
 }
@@ -47,6 +47,23 @@ deploy() { local contract=$1;
             --network local
 }
 
+# upload a contract's wasm to soroban, printing its hash
+install() { local contract=$1;
+    soroban contract install \
+            --wasm "target/wasm32-unknown-unknown/release/$contract.wasm" \
+            --source local-deployer \
+            --network local
+}
+
+# call a function of a deployed contract as local-deployer
+invoke() { local id=$1; shift;
+    soroban contract invoke \
+            --id "$id" \
+            --source local-deployer \
+            --network local \
+            -- "$@"
+}
+
 # airdrop local-deployer once the validator is ready
 await-startup-and-airdrop-deployer() {
     await-startup 0
@@ -62,13 +79,20 @@ await-startup-and-airdrop-deployer() {
     done
 }
 
-# start a fresh validator and deploy our contract
+# start a fresh validator, deploy the factory and router, upload the market wasm, and
+# initialize the factory with it, administered by local-deployer
 full() {
     stop && sleep 0.1 || true
     config-cli
     start
     await-startup-and-airdrop-deployer
-    deploy dex_market
+    local factory market_wasm_hash
+    factory=$(deploy dex_factory)
+    deploy dex_router
+    market_wasm_hash=$(install dex_market)
+    invoke "$factory" init \
+        --admin "$(soroban config identity address local-deployer)" \
+        --market_wasm_hash "$market_wasm_hash"
 }
 
 